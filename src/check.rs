@@ -0,0 +1,455 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml::de::{DeTable, DeValue};
+
+use crate::config::{Config, MAX_BUTTON_LABEL_LEN, MAX_BUTTON_URL_LEN};
+use crate::dirs::get_config_path;
+
+/// Placeholders accepted in text fields.
+pub const PLACEHOLDERS: &[&str] = &[
+    "filename",
+    "workspace",
+    "language",
+    "editor",
+    "repository",
+    "errors",
+    "warnings",
+];
+
+/// Discord rejects activity text longer than this.
+const MAX_TEXT_LEN: usize = 128;
+const MAX_ASSET_KEY_LEN: usize = 256;
+
+/// Values used to render templates when estimating their length; long-ish on
+/// purpose so that templates which only fit for short names are caught.
+const SAMPLE_FILENAME: &str = "configuration_manager.rs";
+const SAMPLE_WORKSPACE: &str = "discord-lsp-presence";
+const SAMPLE_LANGUAGE: &str = "TypeScript";
+const SAMPLE_REPOSITORY: &str = "https://github.com/NotAJocke/discord-lsp-presence";
+const SAMPLE_COUNT: &str = "999";
+
+/// What a key holds, deciding how its value is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Table,
+    ArrayOfTables,
+    /// Activity text accepting placeholders.
+    Text,
+    AssetKey,
+    ButtonLabel,
+    ButtonUrl,
+    /// Anything else; its type is checked by deserializing the config.
+    Value,
+}
+
+/// Every key the config file accepts, as dotted paths where `[]` stands for
+/// the elements of an array of tables and `*` for a profile name. Profiles'
+/// `activity` and `privacy` tables take the same keys as the top-level ones.
+pub const KNOWN_KEYS: &[(&str, FieldKind)] = &[
+    ("application_id", FieldKind::Value),
+    ("time_tracking", FieldKind::Value),
+    ("editor_name", FieldKind::Value),
+    ("enabled", FieldKind::Value),
+    ("log_level", FieldKind::Value),
+    ("focus_hints", FieldKind::Value),
+    ("activity", FieldKind::Table),
+    ("activity.details", FieldKind::Text),
+    ("activity.state", FieldKind::Text),
+    ("activity.large_image_key", FieldKind::AssetKey),
+    ("activity.large_image_text", FieldKind::Text),
+    ("activity.editor_image_key", FieldKind::AssetKey),
+    ("activity.editor_image_text", FieldKind::Text),
+    ("activity.language_images", FieldKind::Value),
+    ("activity.repository_button", FieldKind::Value),
+    ("activity.buttons", FieldKind::ArrayOfTables),
+    ("activity.buttons[].label", FieldKind::ButtonLabel),
+    ("activity.buttons[].url", FieldKind::ButtonUrl),
+    ("activity.activity_type", FieldKind::Value),
+    ("activity.party", FieldKind::Table),
+    ("activity.party.source", FieldKind::Value),
+    ("activity.party.max", FieldKind::Value),
+    ("activity.empty", FieldKind::Table),
+    ("activity.empty.details", FieldKind::Text),
+    ("activity.empty.state", FieldKind::Text),
+    ("activity.empty.large_image_key", FieldKind::AssetKey),
+    ("activity.empty.large_image_text", FieldKind::Text),
+    ("activity.viewing", FieldKind::Table),
+    ("activity.viewing.details", FieldKind::Text),
+    ("activity.viewing.state", FieldKind::Text),
+    ("activity.viewing.small_image_key", FieldKind::AssetKey),
+    ("activity.viewing.small_image_text", FieldKind::Text),
+    ("activity.editing", FieldKind::Table),
+    ("activity.editing.details", FieldKind::Text),
+    ("activity.editing.state", FieldKind::Text),
+    ("activity.editing.small_image_key", FieldKind::AssetKey),
+    ("activity.editing.small_image_text", FieldKind::Text),
+    ("schedule", FieldKind::Table),
+    ("schedule.quiet_hours", FieldKind::ArrayOfTables),
+    ("schedule.quiet_hours[].days", FieldKind::Value),
+    ("schedule.quiet_hours[].start", FieldKind::Value),
+    ("schedule.quiet_hours[].end", FieldKind::Value),
+    ("privacy", FieldKind::Table),
+    ("privacy.private", FieldKind::Value),
+    ("privacy.blocklist", FieldKind::Value),
+    ("profile", FieldKind::Value),
+    ("profiles", FieldKind::Table),
+    ("profiles.*", FieldKind::Table),
+    ("profiles.*.workspaces", FieldKind::Value),
+    ("profiles.*.time_tracking", FieldKind::Value),
+    ("profiles.*.activity", FieldKind::Table),
+    ("profiles.*.privacy", FieldKind::Table),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a config file, with the byte range it applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+impl Problem {
+    fn error(message: String, span: Range<usize>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            span,
+        }
+    }
+
+    fn warning(message: String, span: Range<usize>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            span,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Checks the contents of a config file, returning problems in the order
+/// they appear.
+pub fn check(text: &str) -> Vec<Problem> {
+    let (table, errors) = DeTable::parse_recoverable(text);
+    let mut problems: Vec<Problem> = errors
+        .iter()
+        .map(|e| Problem::error(e.message().to_string(), e.span().unwrap_or(0..0)))
+        .collect();
+
+    if problems.is_empty() {
+        check_table(text, table.get_ref(), "", &mut problems);
+
+        match toml::from_str::<Config>(text) {
+            Ok(config) => {
+                if let Some(name) = config.profile.as_deref()
+                    && !config.has_profile(name)
+                {
+                    let span = table
+                        .get_ref()
+                        .iter()
+                        .find(|(key, _)| key.get_ref() == "profile")
+                        .map_or(0..0, |(_, value)| value.span());
+                    problems.push(Problem::warning(
+                        format!("unknown profile `{}`", name),
+                        span,
+                    ));
+                }
+            }
+            Err(e) => {
+                problems.push(Problem::error(
+                    e.message().to_string(),
+                    e.span().unwrap_or(0..0),
+                ));
+            }
+        }
+    }
+
+    problems.sort_by_key(|p| p.span.start);
+    problems.dedup();
+    problems
+}
+
+/// Maps a key path to its entry in [`KNOWN_KEYS`], replacing profile names
+/// with `*` and keys inside a profile's sections with the top-level ones.
+pub fn known_key_path(path: &str) -> String {
+    let Some(profile_path) = path.strip_prefix("profiles.") else {
+        return path.to_string();
+    };
+    let Some((_, rest)) = profile_path.split_once('.') else {
+        return "profiles.*".to_string();
+    };
+    let in_section = ["activity.", "privacy."]
+        .iter()
+        .any(|section| rest.starts_with(section));
+    if in_section {
+        rest.to_string()
+    } else {
+        format!("profiles.*.{}", rest)
+    }
+}
+
+fn key_kind(path: &str) -> Option<FieldKind> {
+    let path = known_key_path(path);
+    KNOWN_KEYS
+        .iter()
+        .find(|(key, _)| *key == path)
+        .map(|(_, kind)| *kind)
+}
+
+fn check_table(text: &str, table: &DeTable, prefix: &str, problems: &mut Vec<Problem>) {
+    for (key, value) in table.iter() {
+        let path = if prefix.is_empty() {
+            key.get_ref().to_string()
+        } else {
+            format!("{}.{}", prefix, key.get_ref())
+        };
+
+        let Some(kind) = key_kind(&path) else {
+            problems.push(Problem::warning(
+                format!("unknown key `{}`", path),
+                key.span(),
+            ));
+            continue;
+        };
+
+        match (kind, value.get_ref()) {
+            (FieldKind::Table, DeValue::Table(inner)) => {
+                check_table(text, inner, &path, problems);
+            }
+            (FieldKind::ArrayOfTables, DeValue::Array(items)) => {
+                let item_path = format!("{}[]", path);
+                for item in items.iter() {
+                    if let DeValue::Table(inner) = item.get_ref() {
+                        check_table(text, inner, &item_path, problems);
+                    }
+                }
+            }
+            (_, DeValue::String(value_text)) => {
+                check_string(text, kind, value_text, value.span(), problems);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn check_string(
+    text: &str,
+    kind: FieldKind,
+    value: &str,
+    span: Range<usize>,
+    problems: &mut Vec<Problem>,
+) {
+    match kind {
+        FieldKind::Text | FieldKind::ButtonLabel | FieldKind::ButtonUrl => {
+            let mut known = true;
+            for placeholder in find_placeholders(value) {
+                if !PLACEHOLDERS.contains(&placeholder) {
+                    known = false;
+                    let needle = format!("{{{}}}", placeholder);
+                    let placeholder_span = text
+                        .get(span.clone())
+                        .and_then(|source| source.find(&needle))
+                        .map(|offset| span.start + offset..span.start + offset + needle.len())
+                        .unwrap_or_else(|| span.clone());
+                    problems.push(Problem::warning(
+                        format!("unknown placeholder `{}`", needle),
+                        placeholder_span,
+                    ));
+                }
+            }
+
+            let max = match kind {
+                FieldKind::ButtonLabel => MAX_BUTTON_LABEL_LEN,
+                FieldKind::ButtonUrl => MAX_BUTTON_URL_LEN,
+                _ => MAX_TEXT_LEN,
+            };
+            let length = render_sample(value).chars().count();
+            if known && length > max {
+                problems.push(Problem::warning(
+                    format!(
+                        "renders to about {} characters, more than Discord's limit of {}",
+                        length, max
+                    ),
+                    span,
+                ));
+            }
+        }
+        FieldKind::AssetKey => {
+            if let Err(reason) = validate_asset_key(value) {
+                problems.push(Problem::error(
+                    format!("invalid asset key `{}`: {}", value, reason),
+                    span,
+                ));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the names of the `{placeholders}` in `text`.
+pub fn find_placeholders(text: &str) -> impl Iterator<Item = &str> {
+    text.split('{').skip(1).filter_map(|rest| {
+        let (name, _) = rest.split_once('}')?;
+        let is_name =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        is_name.then_some(name)
+    })
+}
+
+fn render_sample(text: &str) -> String {
+    text.replace("{filename}", SAMPLE_FILENAME)
+        .replace("{workspace}", SAMPLE_WORKSPACE)
+        .replace("{language}", SAMPLE_LANGUAGE)
+        .replace("{editor}", "Helix")
+        .replace("{repository}", SAMPLE_REPOSITORY)
+        .replace("{errors}", SAMPLE_COUNT)
+        .replace("{warnings}", SAMPLE_COUNT)
+}
+
+/// Discord matches uploaded assets by their lowercased name; external images
+/// are given as URLs instead.
+fn validate_asset_key(key: &str) -> Result<(), &'static str> {
+    if key.starts_with("https://") || key.starts_with("http://") || key.starts_with("mp:") {
+        return Ok(());
+    }
+    if key.is_empty() {
+        return Err("asset keys cannot be empty");
+    }
+    if key.len() > MAX_ASSET_KEY_LEN {
+        return Err("asset keys are at most 256 characters");
+    }
+    if !key
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        return Err("asset keys are lowercase letters, digits, `_` and `-`, or an image URL");
+    }
+    Ok(())
+}
+
+/// Converts a byte offset into a 1-based line and column.
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |last| last.chars().count())
+        + 1;
+    (line, column)
+}
+
+/// Entry point of `discord-lsp-presence check-config`, returning the process
+/// exit code.
+pub fn run_check_config(args: &[String]) -> i32 {
+    let path = match args {
+        [] => match get_config_path() {
+            Some(path) => path,
+            None => {
+                eprintln!("Could not determine the config file location.");
+                return 2;
+            }
+        },
+        [path] => PathBuf::from(path),
+        _ => {
+            eprintln!("Usage: discord-lsp-presence check-config [PATH]");
+            return 2;
+        }
+    };
+
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return 1;
+        }
+    };
+
+    let problems = check(&text);
+    print_problems(&path, &text, &problems);
+    i32::from(!problems.is_empty())
+}
+
+fn print_problems(path: &Path, text: &str, problems: &[Problem]) {
+    if problems.is_empty() {
+        println!("{}: OK", path.display());
+        return;
+    }
+
+    for problem in problems {
+        let (line, column) = line_col(text, problem.span.start);
+        println!(
+            "{}:{}:{}: {}: {}",
+            path.display(),
+            line,
+            column,
+            problem.severity,
+            problem.message
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_unknown_keys_and_placeholders() {
+        let text = "[activity]\ndetails = \"In {file}\"\nsmall_image_key = \"Rust\"\n";
+        let problems = check(text);
+
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].message, "unknown placeholder `{file}`");
+        assert_eq!(line_col(text, problems[0].span.start), (2, 15));
+        assert_eq!(
+            problems[1].message,
+            "unknown key `activity.small_image_key`"
+        );
+        assert_eq!(line_col(text, problems[1].span.start), (3, 1));
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        let text = "time_tracking = \"daily\"\n[activity]\nlarge_image_key = \"Helix Logo\"\n";
+        let problems = check(text);
+
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].message.contains("daily"));
+        assert!(problems[1].message.starts_with("invalid asset key"));
+    }
+
+    #[test]
+    fn checks_profiles_like_the_top_level() {
+        let text = "profile = \"work\"\n\
+                    [profiles.stream.activity]\n\
+                    details = \"{file}\"\n\
+                    [profiles.stream.privacy]\n\
+                    private = true\n\
+                    [profiles.stream.party]\n";
+        let messages: Vec<_> = check(text).into_iter().map(|p| p.message).collect();
+
+        assert_eq!(
+            messages,
+            [
+                "unknown profile `work`",
+                "unknown placeholder `{file}`",
+                "unknown key `profiles.stream.party`",
+            ]
+        );
+    }
+}
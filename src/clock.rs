@@ -0,0 +1,124 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest a timer sleeps before checking the clock again, so one spanning
+/// a suspend fires soon after resume instead of that much later.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Source of time for time tracking, pauses and status expiry.
+pub trait Clock: Send + Sync {
+    /// Time since an arbitrary point, which keeps counting while the system
+    /// is suspended.
+    fn now(&self) -> Duration;
+
+    /// Wall-clock time since the Unix epoch.
+    fn unix_now(&self) -> Duration;
+
+    /// Converts a reading of [`Clock::now`] into a Unix timestamp in seconds.
+    fn to_unix(&self, at: Duration) -> u64 {
+        let now = self.now();
+        let unix = match at.checked_sub(now) {
+            Some(ahead) => self.unix_now() + ahead,
+            None => self.unix_now().saturating_sub(now - at),
+        };
+        unix.as_secs()
+    }
+
+    /// Converts a Unix timestamp in seconds into a reading of [`Clock::now`].
+    fn at_unix(&self, timestamp: u64) -> Duration {
+        let elapsed = self
+            .unix_now()
+            .saturating_sub(Duration::from_secs(timestamp));
+        self.now().saturating_sub(elapsed)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The system clock.
+///
+/// `Instant` stops while the system is suspended, so timers measured with it
+/// would appear to have started later after every resume. This measures
+/// elapsed time with `CLOCK_BOOTTIME` on Linux and `CLOCK_MONOTONIC` on
+/// macOS, which both include time asleep.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        boot_time()
+    }
+
+    fn unix_now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn boot_time() -> Duration {
+    #[cfg(target_os = "macos")]
+    const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC;
+    #[cfg(not(target_os = "macos"))]
+    const CLOCK: libc::clockid_t = libc::CLOCK_BOOTTIME;
+
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `clock_gettime` only writes into the provided `timespec`.
+    unsafe { libc::clock_gettime(CLOCK, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn boot_time() -> Duration {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed()
+}
+
+/// Sleeps until `clock` reaches `deadline`.
+pub async fn sleep_until(clock: &dyn Clock, deadline: Duration) {
+    loop {
+        let remaining = deadline.saturating_sub(clock.now());
+        if remaining.is_zero() {
+            return;
+        }
+        tokio::time::sleep(remaining.min(RECHECK_INTERVAL)).await;
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Arc<std::sync::Mutex<Duration>>,
+    unix_start: Duration,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// Creates a clock reading `unix_start` seconds since the Unix epoch.
+    pub fn new(unix_start: u64) -> Self {
+        Self {
+            now: Arc::new(std::sync::Mutex::new(Duration::ZERO)),
+            unix_start: Duration::from_secs(unix_start),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn unix_now(&self) -> Duration {
+        self.unix_start + self.now()
+    }
+}
@@ -1,87 +1,334 @@
-use discord_presence::models::rich_presence::{Activity, ActivityAssets, ActivityTimestamps};
+use discord_presence::models::rich_presence::{
+    Activity, ActivityAssets, ActivityTimestamps, ActivityType,
+};
+use schemars::JsonSchema;
 use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::env::home_dir;
-use std::path::PathBuf;
+use std::path::Path;
 
+use crate::dirs::get_config_path;
 use crate::language::LanguageInfo;
-
-pub fn get_config_dir() -> Option<PathBuf> {
-    home_dir().map(|home| home.join(".config").join("discord-presence-lsp"))
-}
+use crate::logging::LogLevel;
+use crate::schedule::ScheduleConfig;
+use crate::state::DiagnosticCounts;
+use crate::workspace::glob_match;
 
 const DEFAULT_APPLICATION_ID: u64 = 1470506076574187745;
 const DEFAULT_DETAILS: &str = "Editing: {filename}";
 const DEFAULT_STATE: &str = "in {workspace}";
+const DEFAULT_VIEWING_DETAILS: &str = "Viewing: {filename}";
+const DEFAULT_EMPTY_DETAILS: &str = "Idle in {editor}";
+const DEFAULT_EMPTY_STATE: &str = "in {workspace}";
 const DEFAULT_EDITOR_NAME: &str = "Helix";
+const REPOSITORY_BUTTON_LABEL: &str = "View repository";
+const MAX_BUTTONS: usize = 2;
+pub const MAX_BUTTON_LABEL_LEN: usize = 32;
+pub const MAX_BUTTON_URL_LEN: usize = 512;
 
-pub fn get_config_path() -> Option<PathBuf> {
-    get_config_dir().map(|dir| dir.join("config.toml"))
-}
-
-#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum TimeTracking {
+    /// The timer restarts when switching files.
     #[default]
     File,
+    /// The timer restarts only when switching workspaces.
     Workspace,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
 pub struct ActivityConfig {
+    /// Top line, `"Editing: {filename}"` or `"Viewing: {filename}"` by default.
     pub details: Option<String>,
+    /// Bottom line, `"in {workspace}"` by default.
     pub state: Option<String>,
     pub large_image_key: Option<String>,
     pub large_image_text: Option<String>,
+    /// Large image, preferred over `large_image_key`.
     pub editor_image_key: Option<String>,
+    /// Large image text, preferred over `large_image_text`.
     pub editor_image_text: Option<String>,
+    /// Show the language icon as the small image (default `true`).
+    #[schemars(extend("default" = true))]
     pub language_images: Option<bool>,
+    /// Link the workspace's repository with a button (default `false`).
+    #[schemars(extend("default" = false))]
+    pub repository_button: Option<bool>,
+    /// Up to two buttons, including the repository button.
+    pub buttons: Option<Vec<ButtonConfig>>,
+    pub activity_type: Option<ActivityKind>,
+    /// Show a party size next to the activity.
+    pub party: Option<PartyConfig>,
+    pub empty: Option<EmptyActivityConfig>,
+    pub viewing: Option<ModeConfig>,
+    pub editing: Option<ModeConfig>,
+}
+
+/// Overrides applied while a document is only being viewed (opened or saved,
+/// no edits since) or being edited (modified since it was opened or saved).
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
+pub struct ModeConfig {
+    pub details: Option<String>,
+    pub state: Option<String>,
+    pub small_image_key: Option<String>,
+    pub small_image_text: Option<String>,
+}
+
+/// Templates used while no document is open, e.g. on the editor's start
+/// screen or after closing every file.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
+pub struct EmptyActivityConfig {
+    pub details: Option<String>,
+    pub state: Option<String>,
+    pub large_image_key: Option<String>,
+    pub large_image_text: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActivityKind {
+    Playing,
+    Listening,
+    Watching,
+    Competing,
 }
 
-#[derive(Deserialize, Debug, Default)]
+impl From<ActivityKind> for ActivityType {
+    fn from(kind: ActivityKind) -> Self {
+        match kind {
+            ActivityKind::Playing => ActivityType::Playing,
+            ActivityKind::Listening => ActivityType::Listening,
+            ActivityKind::Watching => ActivityType::Watching,
+            ActivityKind::Competing => ActivityType::Competing,
+        }
+    }
+}
+
+/// What the party size shown next to the activity counts.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PartySource {
+    /// Documents open in this editor.
+    #[default]
+    Documents,
+    /// Editor processes working on the same workspace.
+    Instances,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
+pub struct PartyConfig {
+    #[schemars(extend("default" = "documents"))]
+    pub source: Option<PartySource>,
+    /// Party capacity; defaults to the current size.
+    pub max: Option<u32>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
+pub struct ButtonConfig {
+    pub label: String,
+    pub url: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
+pub struct PrivacyConfig {
+    /// Never link to repositories, whatever the workspace.
+    #[schemars(extend("default" = false))]
+    pub private: Option<bool>,
+    /// Workspace names (globs allowed) whose repository is never linked.
+    pub blocklist: Option<Vec<String>>,
+}
+
+/// A named alternative look, e.g. for streaming or work. Each section a
+/// profile sets replaces the top-level section of the same name while the
+/// profile is in use.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// Workspace names or paths (globs allowed, `~/` for the home directory)
+    /// that select this profile.
+    pub workspaces: Option<Vec<String>>,
+    pub activity: Option<ActivityConfig>,
+    pub privacy: Option<PrivacyConfig>,
+    pub time_tracking: Option<TimeTracking>,
+}
+
+/// Everything the activity templates can refer to.
+#[derive(Debug, Clone, Copy)]
+pub struct ActivityContext<'a> {
+    pub filename: &'a str,
+    pub workspace: &'a str,
+    pub language: &'a LanguageInfo,
+    pub repository: Option<&'a str>,
+    /// Whether the document was modified since it was opened or last saved.
+    pub editing: bool,
+    pub diagnostics: DiagnosticCounts,
+}
+
+/// Settings from `config.toml`, merged with those sent by the editor.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(
+    title = "discord-lsp-presence configuration",
+    deny_unknown_fields,
+    transform = drop_null_defaults
+)]
 pub struct Config {
+    /// Discord application whose assets the images refer to.
     #[serde(default)]
+    #[schemars(extend("default" = DEFAULT_APPLICATION_ID))]
     pub application_id: Option<u64>,
     #[serde(default)]
     pub activity: Option<ActivityConfig>,
     #[serde(default)]
+    #[schemars(extend("default" = "file"))]
     pub time_tracking: Option<TimeTracking>,
+    /// Name used by the `{editor}` placeholder (default `"Helix"`).
     #[serde(default)]
+    #[schemars(extend("default" = DEFAULT_EDITOR_NAME))]
     pub editor_name: Option<String>,
+    /// Start with presence turned on (default `true`).
     #[serde(default)]
+    #[schemars(extend("default" = true))]
     pub enabled: Option<bool>,
+    #[serde(default)]
+    pub schedule: Option<ScheduleConfig>,
+    #[serde(default)]
+    pub privacy: Option<PrivacyConfig>,
+    /// Overridden by `$DISCORD_LSP_LOG`.
+    #[serde(default)]
+    #[schemars(extend("default" = "info"))]
+    pub log_level: Option<LogLevel>,
+    /// Answer hover and similar requests to learn which document is focused
+    /// (default `true`).
+    #[serde(default)]
+    #[schemars(extend("default" = true))]
+    pub focus_hints: Option<bool>,
+    /// Profile used when none is selected and no profile matches the
+    /// workspace.
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub profiles: Option<BTreeMap<String, ProfileConfig>>,
 }
 
-impl Config {
-    pub fn load() -> Self {
-        let Some(path) = get_config_path() else {
-            return Self::default();
-        };
+/// Removes the `"default": null` that `#[serde(default)]` gives optional
+/// fields, which editors would offer as their default value.
+pub fn drop_null_defaults(schema: &mut schemars::Schema) {
+    let Some(properties) = schema.get_mut("properties").and_then(Value::as_object_mut) else {
+        return;
+    };
+    for property in properties.values_mut().filter_map(Value::as_object_mut) {
+        if property.get("default") == Some(&Value::Null) {
+            property.remove("default");
+        }
+    }
+}
 
-        if !path.exists() {
-            return Self::default();
+/// Entry point of `discord-lsp-presence schema`, which prints the JSON Schema
+/// of the config file for taplo and other TOML tooling.
+pub fn run_schema() -> i32 {
+    match serde_json::to_string_pretty(&schemars::schema_for!(Config)) {
+        Ok(json) => {
+            println!("{}", json);
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to generate the schema: {}", e);
+            1
         }
+    }
+}
 
-        let config_str = match std::fs::read_to_string(&path) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!(
-                    "Warning: Failed to read config file: {}. Using defaults.",
-                    e
-                );
-                return Self::default();
-            }
-        };
+/// Matches a profile's workspace `pattern` against the workspace name or the
+/// path of the document in it.
+fn workspace_matches(pattern: &str, workspace: &str, path: Option<&Path>) -> bool {
+    if glob_match(pattern, workspace) {
+        return true;
+    }
+    let Some(path) = path.and_then(Path::to_str) else {
+        return false;
+    };
+    match pattern.strip_prefix("~/") {
+        Some(rest) => home_dir().is_some_and(|home| {
+            glob_match(&home.join(rest).to_string_lossy(), path)
+        }),
+        None => glob_match(pattern, path),
+    }
+}
+
+/// Reads the config file as JSON, so that settings sent by the editor can be
+/// merged over it. Returns `Null` if the file is missing or invalid, warning
+/// about the latter.
+pub fn load_file_settings() -> Value {
+    let Some(path) = get_config_path() else {
+        return Value::Null;
+    };
 
-        match toml::from_str(&config_str) {
-            Ok(config) => config,
-            Err(e) => {
-                eprintln!(
-                    "Warning: Failed to parse config file: {}. Using defaults.",
-                    e
-                );
-                Self::default()
+    if !path.exists() {
+        return Value::Null;
+    }
+
+    let config_str = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!(
+                "Warning: Failed to read config file: {}. Using defaults.",
+                e
+            );
+            return Value::Null;
+        }
+    };
+
+    if let Err(e) = toml::from_str::<Config>(&config_str) {
+        eprintln!(
+            "Warning: Failed to parse config file: {}. Using defaults. \
+             Run `discord-lsp-presence check-config` for details.",
+            e
+        );
+        return Value::Null;
+    }
+
+    toml::from_str::<toml::Table>(&config_str)
+        .ok()
+        .and_then(|table| serde_json::to_value(table).ok())
+        .unwrap_or(Value::Null)
+}
+
+/// Merges `overlay` into `base`: tables are merged key by key, `null` leaves
+/// the existing value alone and anything else replaces it.
+fn merge_settings(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (_, Value::Null) => {}
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_settings(base.entry(key.clone()).or_insert(Value::Null), value);
             }
         }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+impl Config {
+    /// Builds the config from the file's settings with the editor's settings
+    /// merged over them, so that the editor wins wherever both set a key.
+    /// The editor's settings are the `initializationOptions` at first, and
+    /// each `didChangeConfiguration` replaces them rather than merging over
+    /// them. Editors that send all of their settings may nest ours under
+    /// `discord-presence`.
+    pub fn from_settings(file: &Value, editor: &Value) -> Result<Self, serde_json::Error> {
+        let editor = editor.get("discord-presence").unwrap_or(editor);
+        let mut merged = file.clone();
+        merge_settings(&mut merged, editor);
+        if merged.is_null() {
+            return Ok(Self::default());
+        }
+        serde_json::from_value(merged)
     }
 
     pub fn get_application_id(&self) -> u64 {
@@ -107,43 +354,128 @@ impl Config {
         self.enabled.unwrap_or(true)
     }
 
-    pub fn build_details_and_state(
-        &self,
-        filename: &str,
+    /// Whether to answer hover, highlight and similar requests (with empty
+    /// results) to learn which document is focused.
+    pub fn use_focus_hints(&self) -> bool {
+        self.focus_hints.unwrap_or(true)
+    }
+
+    pub fn get_party(&self) -> Option<&PartyConfig> {
+        self.activity.as_ref().and_then(|a| a.party.as_ref())
+    }
+
+    pub fn get_empty_activity(&self) -> Option<&EmptyActivityConfig> {
+        self.activity.as_ref().and_then(|a| a.empty.as_ref())
+    }
+
+    pub fn is_repository_hidden(&self, workspace: &str) -> bool {
+        let Some(privacy) = &self.privacy else {
+            return false;
+        };
+        privacy.private.unwrap_or(false)
+            || privacy
+                .blocklist
+                .iter()
+                .flatten()
+                .any(|pattern| glob_match(pattern, workspace))
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        self.profiles.as_ref().is_some_and(|p| p.contains_key(name))
+    }
+
+    /// Picks the profile for a document at `path` in `workspace`: the one
+    /// `selected` at runtime, else the first one (by name) whose workspaces
+    /// match, else the default `profile`.
+    pub fn select_profile<'a>(
+        &'a self,
+        selected: Option<&'a str>,
         workspace: &str,
-        language: &LanguageInfo,
-    ) -> (String, String) {
+        path: Option<&Path>,
+    ) -> Option<&'a str> {
+        let profiles = self.profiles.as_ref()?;
+        if let Some(name) = selected.filter(|name| profiles.contains_key(*name)) {
+            return Some(name);
+        }
+        profiles
+            .iter()
+            .find(|(_, profile)| {
+                profile
+                    .workspaces
+                    .iter()
+                    .flatten()
+                    .any(|pattern| workspace_matches(pattern, workspace, path))
+            })
+            .map(|(name, _)| name.as_str())
+            .or_else(|| self.profile.as_deref().filter(|name| profiles.contains_key(*name)))
+    }
+
+    /// Returns a copy of this config with the sections set by profile `name`
+    /// in place of the top-level ones.
+    pub fn with_profile(&self, name: &str) -> Self {
+        let mut config = self.clone();
+        let Some(profile) = self.profiles.as_ref().and_then(|p| p.get(name)) else {
+            return config;
+        };
+        if profile.activity.is_some() {
+            config.activity = profile.activity.clone();
+        }
+        if profile.privacy.is_some() {
+            config.privacy = profile.privacy.clone();
+        }
+        if profile.time_tracking.is_some() {
+            config.time_tracking = profile.time_tracking;
+        }
+        config
+    }
+
+    pub fn get_schedule(&self) -> Option<&ScheduleConfig> {
+        self.schedule.as_ref().filter(|s| !s.is_empty())
+    }
+
+    fn get_mode(&self, editing: bool) -> ModeConfig {
+        let activity_config = self.activity.as_ref();
+        let mode = if editing {
+            activity_config.and_then(|a| a.editing.clone())
+        } else {
+            activity_config.and_then(|a| a.viewing.clone())
+        };
+        mode.unwrap_or_default()
+    }
+
+    pub fn build_details_and_state(&self, ctx: &ActivityContext) -> (String, String) {
         let activity_config = self.activity.clone().unwrap_or_default();
-        let editor_name = self.get_editor_name();
-        let details_template = activity_config
+        let mode = self.get_mode(ctx.editing);
+        // A custom `details` applies to both modes; only the built-in default
+        // distinguishes viewing from editing.
+        let default_details = if ctx.editing {
+            DEFAULT_DETAILS
+        } else {
+            DEFAULT_VIEWING_DETAILS
+        };
+        let details_template = mode
             .details
-            .unwrap_or_else(|| DEFAULT_DETAILS.to_string());
-        let state_template = activity_config
+            .or(activity_config.details)
+            .unwrap_or_else(|| default_details.to_string());
+        let state_template = mode
             .state
+            .or(activity_config.state)
             .unwrap_or_else(|| DEFAULT_STATE.to_string());
 
-        let details = replace_placeholders(
-            &details_template,
-            filename,
-            workspace,
-            language,
-            editor_name,
-        );
-        let state =
-            replace_placeholders(&state_template, filename, workspace, language, editor_name);
+        let details = self.render(&details_template, ctx);
+        let state = self.render(&state_template, ctx);
         (details, state)
     }
 
-    pub fn build_activity(
-        &self,
-        filename: &str,
-        workspace: &str,
-        language: &LanguageInfo,
-        start_timestamp: Option<u64>,
-    ) -> Activity {
+    pub fn build_activity(&self, ctx: &ActivityContext, start_timestamp: Option<u64>) -> Activity {
         let activity_config = self.activity.clone().unwrap_or_default();
-        let editor_name = self.get_editor_name();
-        let (details, state) = self.build_details_and_state(filename, workspace, language);
+        let mode = self.get_mode(ctx.editing);
+        let hidden = self.is_repository_hidden(ctx.workspace);
+        let ctx = &ActivityContext {
+            repository: ctx.repository.filter(|_| !hidden),
+            ..*ctx
+        };
+        let (details, state) = self.build_details_and_state(ctx);
 
         let large_image_key = activity_config
             .editor_image_key
@@ -151,17 +483,27 @@ impl Config {
         let large_image_text = activity_config
             .editor_image_text
             .or(activity_config.large_image_text)
-            .map(|text| replace_placeholders(&text, filename, workspace, language, editor_name));
+            .map(|text| self.render(&text, ctx));
 
-        let small_image_key = if self.show_language_images() && !language.icon_key.is_empty() {
-            Some(language.icon_key.clone())
+        let small_image_key = if mode.small_image_key.is_some() {
+            mode.small_image_key
+        } else if self.show_language_images() && !ctx.language.icon_key.is_empty() {
+            Some(ctx.language.icon_key.clone())
         } else {
             None
         };
-        let small_image_text = small_image_key.as_ref().map(|_| language.name.clone());
+        let small_image_text = small_image_key.as_ref().map(|_| {
+            mode.small_image_text
+                .map(|text| self.render(&text, ctx))
+                .unwrap_or_else(|| ctx.language.name.clone())
+        });
 
         let mut builder = Activity::new().details(details).state(state);
 
+        if let Some(kind) = activity_config.activity_type {
+            builder = builder.activity_type(kind.into());
+        }
+
         if let Some(ts) = start_timestamp {
             builder = builder.timestamps(|_| ActivityTimestamps::new().start(ts));
         }
@@ -185,19 +527,213 @@ impl Config {
             });
         }
 
+        for (label, url) in self.build_buttons(ctx) {
+            builder = builder.append_buttons(|button| button.label(label).url(url));
+        }
+
         builder
     }
+
+    /// Builds the activity shown while no document is open, or `None` when
+    /// `[activity.empty]` is not configured. `{filename}` and `{language}`
+    /// render as empty strings.
+    pub fn build_empty_activity(
+        &self,
+        workspace: &str,
+        repository: Option<&str>,
+        start_timestamp: Option<u64>,
+    ) -> Option<Activity> {
+        let empty_config = self.get_empty_activity()?.clone();
+        let activity_config = self.activity.clone().unwrap_or_default();
+        let language = LanguageInfo {
+            name: String::new(),
+            icon_key: String::new(),
+        };
+        let ctx = &ActivityContext {
+            filename: "",
+            workspace,
+            language: &language,
+            repository: repository.filter(|_| !self.is_repository_hidden(workspace)),
+            editing: false,
+            diagnostics: DiagnosticCounts::default(),
+        };
+
+        let details_template = empty_config.details.as_deref();
+        let state_template = empty_config.state.as_deref();
+        let details = self.render(details_template.unwrap_or(DEFAULT_EMPTY_DETAILS), ctx);
+        let state = self.render(state_template.unwrap_or(DEFAULT_EMPTY_STATE), ctx);
+        let large_image_key = empty_config
+            .large_image_key
+            .or(activity_config.editor_image_key)
+            .or(activity_config.large_image_key);
+        let large_image_text = empty_config
+            .large_image_text
+            .or(activity_config.editor_image_text)
+            .or(activity_config.large_image_text)
+            .map(|text| self.render(&text, ctx));
+
+        let mut builder = Activity::new().details(details).state(state);
+
+        if let Some(kind) = activity_config.activity_type {
+            builder = builder.activity_type(kind.into());
+        }
+
+        if let Some(ts) = start_timestamp {
+            builder = builder.timestamps(|_| ActivityTimestamps::new().start(ts));
+        }
+
+        if let Some(key) = large_image_key {
+            builder = builder.assets(|_| {
+                let mut assets = ActivityAssets::new().large_image(key);
+                if let Some(t) = large_image_text {
+                    assets = assets.large_text(t);
+                }
+                assets
+            });
+        }
+
+        for (label, url) in self.build_buttons(ctx) {
+            builder = builder.append_buttons(|button| button.label(label).url(url));
+        }
+
+        Some(builder)
+    }
+
+    /// Builds up to two `(label, url)` buttons, the built-in repository
+    /// button first. Buttons referring to `{repository}` are skipped when no
+    /// repository may be linked.
+    fn build_buttons(&self, ctx: &ActivityContext) -> Vec<(String, String)> {
+        let activity_config = self.activity.clone().unwrap_or_default();
+        let mut buttons = Vec::new();
+
+        if activity_config.repository_button.unwrap_or(false)
+            && let Some(url) = ctx.repository
+        {
+            buttons.push((REPOSITORY_BUTTON_LABEL.to_string(), url.to_string()));
+        }
+
+        for button in activity_config.buttons.unwrap_or_default() {
+            let uses_repository =
+                button.label.contains("{repository}") || button.url.contains("{repository}");
+            if uses_repository && ctx.repository.is_none() {
+                continue;
+            }
+
+            let label: String = self
+                .render(&button.label, ctx)
+                .chars()
+                .take(MAX_BUTTON_LABEL_LEN)
+                .collect();
+            let url = self.render(&button.url, ctx);
+            let valid_url = (url.starts_with("https://") || url.starts_with("http://"))
+                && url.len() <= MAX_BUTTON_URL_LEN;
+            if !label.trim().is_empty() && valid_url {
+                buttons.push((label, url));
+            }
+        }
+
+        buttons.truncate(MAX_BUTTONS);
+        buttons
+    }
+
+    fn render(&self, text: &str, ctx: &ActivityContext) -> String {
+        text.replace("{filename}", ctx.filename)
+            .replace("{workspace}", ctx.workspace)
+            .replace("{language}", &ctx.language.name)
+            .replace("{editor}", self.get_editor_name())
+            .replace("{repository}", ctx.repository.unwrap_or_default())
+            .replace("{errors}", &ctx.diagnostics.errors.to_string())
+            .replace("{warnings}", &ctx.diagnostics.warnings.to_string())
+    }
 }
 
-fn replace_placeholders(
-    text: &str,
-    filename: &str,
-    workspace: &str,
-    language: &LanguageInfo,
-    editor: &str,
-) -> String {
-    text.replace("{filename}", filename)
-        .replace("{workspace}", workspace)
-        .replace("{language}", &language.name)
-        .replace("{editor}", editor)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use std::collections::BTreeSet;
+
+    /// Follows `$ref`s and the `anyOf` schemars emits for optional fields.
+    fn resolve<'a>(root: &'a Value, mut node: &'a Value) -> &'a Value {
+        loop {
+            if let Some(name) = node["$ref"].as_str().and_then(|r| r.strip_prefix("#/$defs/")) {
+                node = &root["$defs"][name];
+            } else if let Some(variant) = node["anyOf"]
+                .as_array()
+                .and_then(|variants| variants.iter().find(|v| v["type"] != "null"))
+            {
+                node = variant;
+            } else {
+                return node;
+            }
+        }
+    }
+
+    /// Collects every table key the schema allows under `prefix`, written
+    /// like the entries of [`crate::check::KNOWN_KEYS`].
+    fn schema_keys(root: &Value, node: &Value, prefix: &str, keys: &mut BTreeSet<String>) {
+        let node = resolve(root, node);
+        let join = |key: &str| match prefix {
+            "" => key.to_string(),
+            _ => format!("{}.{}", prefix, key),
+        };
+
+        for (key, property) in node["properties"].as_object().into_iter().flatten() {
+            let path = join(key);
+            keys.insert(crate::check::known_key_path(&path));
+            schema_keys(root, property, &path, keys);
+        }
+        let items = resolve(root, &node["items"]);
+        if items["properties"].is_object() {
+            schema_keys(root, items, &format!("{}[]", prefix), keys);
+        }
+        let values = resolve(root, &node["additionalProperties"]);
+        if values["properties"].is_object() {
+            keys.insert(join("*"));
+            schema_keys(root, values, &join("*"), keys);
+        }
+    }
+
+    #[test]
+    fn schema_matches_the_checked_keys() {
+        let schema = serde_json::to_value(schemars::schema_for!(Config)).unwrap();
+        let mut in_schema = BTreeSet::new();
+        schema_keys(&schema, &schema, "", &mut in_schema);
+        let checked: BTreeSet<String> = crate::check::KNOWN_KEYS
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .collect();
+
+        let missing: Vec<_> = checked.difference(&in_schema).collect();
+        assert!(missing.is_empty(), "missing from the schema: {:?}", missing);
+        let unchecked: Vec<_> = in_schema.difference(&checked).collect();
+        assert!(unchecked.is_empty(), "missing from KNOWN_KEYS: {:?}", unchecked);
+
+        let tables = std::iter::once(&schema).chain(schema["$defs"].as_object().unwrap().values());
+        for table in tables.filter(|table| table["properties"].is_object()) {
+            assert_eq!(table["additionalProperties"], false, "{}", table);
+        }
+        assert!(!schema.to_string().contains(r#""default":null"#));
+    }
+
+    #[test]
+    fn editor_settings_override_the_file_key_by_key() {
+        let file = json!({
+            "editor_name": "Helix",
+            "activity": { "details": "From file", "state": "in {workspace}" },
+        });
+        let editor = json!({
+            "discord-presence": {
+                "activity": { "details": "From editor", "state": null },
+                "time_tracking": "workspace",
+            },
+        });
+
+        let config = Config::from_settings(&file, &editor).unwrap();
+        let activity = config.activity.unwrap();
+        assert_eq!(config.editor_name.as_deref(), Some("Helix"));
+        assert_eq!(activity.details.as_deref(), Some("From editor"));
+        assert_eq!(activity.state.as_deref(), Some("in {workspace}"));
+        assert_eq!(config.time_tracking, Some(TimeTracking::Workspace));
+    }
 }
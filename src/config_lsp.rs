@@ -0,0 +1,182 @@
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, Position, Range,
+};
+use url::Url;
+
+use crate::check::{FieldKind, KNOWN_KEYS, PLACEHOLDERS, Severity, check, known_key_path};
+use crate::dirs::get_config_path;
+
+/// Returns whether `uri` is this server's own config file.
+pub fn is_config_file(uri: &Url) -> bool {
+    let (Ok(path), Some(config_path)) = (uri.to_file_path(), get_config_path()) else {
+        return false;
+    };
+    path == config_path
+        || path
+            .canonicalize()
+            .is_ok_and(|path| config_path.canonicalize().is_ok_and(|c| c == path))
+}
+
+/// Checks the config file contents, as LSP diagnostics.
+pub fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    check(text)
+        .into_iter()
+        .map(|problem| Diagnostic {
+            range: Range::new(
+                position_at(text, problem.span.start),
+                position_at(text, problem.span.end),
+            ),
+            severity: Some(match problem.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+            }),
+            source: Some("discord-lsp-presence".to_string()),
+            message: problem.message,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Suggests `{placeholders}` inside strings, table names in headers and the
+/// keys of the enclosing table elsewhere.
+pub fn completions(text: &str, position: Position) -> Vec<CompletionItem> {
+    let line_start = offset_at(text, Position::new(position.line, 0));
+    let before = &text[line_start..offset_at(text, position)];
+
+    let in_string = before.matches('"').count() % 2 == 1;
+    if in_string {
+        let in_placeholder = before
+            .rfind('{')
+            .is_some_and(|open| before.rfind('}').is_none_or(|close| close < open));
+        if !in_placeholder {
+            return Vec::new();
+        }
+        return PLACEHOLDERS
+            .iter()
+            .map(|name| CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::VARIABLE),
+                insert_text: Some(format!("{}}}", name)),
+                ..Default::default()
+            })
+            .collect();
+    }
+
+    if before.trim_start().starts_with('[') {
+        return KNOWN_KEYS
+            .iter()
+            .filter(|(key, kind)| {
+                matches!(kind, FieldKind::Table | FieldKind::ArrayOfTables)
+                    && !key.contains("[]")
+                    && !key.contains('*')
+            })
+            .map(|(key, _)| CompletionItem {
+                label: key.to_string(),
+                kind: Some(CompletionItemKind::MODULE),
+                ..Default::default()
+            })
+            .collect();
+    }
+
+    if before.contains('=') {
+        return Vec::new();
+    }
+
+    let table = known_key_path(&current_table(&text[..line_start]));
+    let prefix = if table.is_empty() {
+        String::new()
+    } else {
+        format!("{}.", table)
+    };
+    KNOWN_KEYS
+        .iter()
+        .filter_map(|(key, kind)| {
+            let name = key.strip_prefix(prefix.as_str())?;
+            let is_child = !name.contains('.') && !name.contains("[]") && name != "*";
+            is_child.then_some((name, kind))
+        })
+        .map(|(name, kind)| CompletionItem {
+            label: name.to_string(),
+            kind: Some(match kind {
+                FieldKind::Table | FieldKind::ArrayOfTables => CompletionItemKind::MODULE,
+                _ => CompletionItemKind::PROPERTY,
+            }),
+            insert_text: Some(format!("{} = ", name)),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Returns the path of the last `[table]` or `[[array]]` header in `text`,
+/// with `[]` appended for arrays of tables.
+fn current_table(text: &str) -> String {
+    text.lines()
+        .rev()
+        .map(str::trim)
+        .find_map(|line| {
+            if let Some(name) = line.strip_prefix("[[") {
+                let name = name.split("]]").next()?;
+                Some(format!("{}[]", name.trim()))
+            } else {
+                let name = line.strip_prefix('[')?.split(']').next()?;
+                Some(name.trim().to_string())
+            }
+        })
+        .unwrap_or_default()
+}
+
+/// Converts a byte offset into an LSP position, counting UTF-16 code units.
+fn position_at(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character = before[line_start..].encode_utf16().count();
+    Position::new(line as u32, character as u32)
+}
+
+/// Converts an LSP position into a byte offset, clamped to the text.
+fn offset_at(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+
+    let line_end = text[line_start..]
+        .find('\n')
+        .map_or(text.len(), |i| line_start + i);
+    let mut units = 0;
+    for (i, c) in text[line_start..line_end].char_indices() {
+        if units >= position.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(items: Vec<CompletionItem>) -> Vec<String> {
+        items.into_iter().map(|item| item.label).collect()
+    }
+
+    #[test]
+    fn completes_keys_and_placeholders() {
+        let text = "[activity.party]\n\n[activity]\ndetails = \"On {\"\n";
+
+        let party_keys = labels(completions(text, Position::new(1, 0)));
+        assert_eq!(party_keys, ["source", "max"]);
+
+        let placeholders = labels(completions(text, Position::new(3, 15)));
+        assert!(placeholders.contains(&"filename".to_string()));
+
+        let activity_keys = labels(completions(text, Position::new(4, 0)));
+        assert!(activity_keys.contains(&"empty".to_string()));
+        assert!(!activity_keys.contains(&"source".to_string()));
+    }
+}
@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::dirs::get_runtime_dir;
+use crate::status::StatusReport;
+
+/// How long [`notify`] waits for a server to accept a request.
+const NOTIFY_TIMEOUT: Duration = Duration::from_millis(200);
+
+const CTL_USAGE: &str =
+    "Usage: discord-lsp-presence ctl <pause [DURATION]|resume|toggle|status|profile [NAME]|set-status [--image KEY] [--expires DURATION] [TEXT]>";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    Pause {
+        #[serde(default)]
+        duration: Option<String>,
+    },
+    Resume,
+    Toggle,
+    Status,
+    /// Selects a profile, or goes back to picking one by workspace.
+    Profile {
+        #[serde(default)]
+        name: Option<String>,
+    },
+    SetStatus {
+        text: Option<String>,
+        #[serde(default)]
+        small_image: Option<String>,
+        #[serde(default)]
+        expires: Option<String>,
+    },
+    /// Sent by `proxy` instances whenever the diagnostics they relay for a
+    /// document change.
+    Diagnostics {
+        uri: String,
+        errors: u32,
+        warnings: u32,
+    },
+}
+
+impl ControlRequest {
+    fn parse(args: &[String]) -> Option<Self> {
+        let (command, rest) = args.split_first()?;
+        let request = match command.as_str() {
+            "pause" if rest.len() <= 1 => {
+                return Some(Self::Pause {
+                    duration: rest.first().cloned(),
+                });
+            }
+            "resume" => Self::Resume,
+            "toggle" => Self::Toggle,
+            "status" => Self::Status,
+            "profile" if rest.len() <= 1 => {
+                return Some(Self::Profile {
+                    name: rest.first().cloned(),
+                });
+            }
+            "set-status" => {
+                let mut small_image = None;
+                let mut expires = None;
+                let mut words = Vec::new();
+                let mut rest = rest.iter();
+                while let Some(arg) = rest.next() {
+                    match arg.as_str() {
+                        "--image" => small_image = Some(rest.next()?.clone()),
+                        "--expires" => expires = Some(rest.next()?.clone()),
+                        _ => words.push(arg.as_str()),
+                    }
+                }
+                let text = words.join(" ");
+                return Some(Self::SetStatus {
+                    text: (!text.is_empty()).then_some(text),
+                    small_image,
+                    expires,
+                });
+            }
+            _ => return None,
+        };
+        rest.is_empty().then_some(request)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ControlResponse {
+    Ok { status: StatusReport },
+    Err { error: String },
+}
+
+pub fn get_socket_path() -> Option<PathBuf> {
+    socket_path_of(std::process::id())
+}
+
+fn socket_path_of(pid: u32) -> Option<PathBuf> {
+    get_runtime_dir().map(|dir| dir.join(format!("{}.sock", pid)))
+}
+
+/// Accepts control connections, answering one JSON request per line.
+pub async fn serve<F, Fut>(handler: F) -> std::io::Result<()>
+where
+    F: Fn(ControlRequest) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = ControlResponse> + Send,
+{
+    let path = get_socket_path()
+        .ok_or_else(|| std::io::Error::other("no usable runtime directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let response = match serde_json::from_str(&line) {
+                    Ok(request) => handler(request).await,
+                    Err(e) => ControlResponse::Err {
+                        error: format!("Invalid request: {}", e),
+                    },
+                };
+                let Ok(mut json) = serde_json::to_string(&response) else {
+                    break;
+                };
+                json.push('\n');
+                if writer.write_all(json.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+pub fn remove_socket() {
+    if let Some(path) = get_socket_path() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Entry point of `discord-lsp-presence ctl`, sending the request to every
+/// running server and returning the process exit code.
+pub async fn run_ctl(args: &[String]) -> i32 {
+    let Some(request) = ControlRequest::parse(args) else {
+        eprintln!("{}", CTL_USAGE);
+        return 2;
+    };
+
+    let responses = broadcast(&request).await;
+    let mut failed = false;
+    for (socket, response) in &responses {
+        match response {
+            Ok(ControlResponse::Ok { status }) => println!("{}", status),
+            Ok(ControlResponse::Err { error }) => {
+                eprintln!("{}: {}", socket.display(), error);
+                failed = true;
+            }
+            Err(e) => {
+                eprintln!("{}: {}", socket.display(), e);
+                failed = true;
+            }
+        }
+    }
+
+    if responses.is_empty() {
+        eprintln!("No running discord-lsp-presence server found.");
+        return 1;
+    }
+    i32::from(failed)
+}
+
+/// Sends `request` to every running server, returning each socket with its
+/// response. Sockets left behind by dead servers are removed.
+pub async fn broadcast(
+    request: &ControlRequest,
+) -> Vec<(PathBuf, std::io::Result<ControlResponse>)> {
+    let sockets = match get_runtime_dir().map(std::fs::read_dir) {
+        Some(Ok(entries)) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sock"))
+            .collect::<Vec<_>>(),
+        _ => Vec::new(),
+    };
+
+    let mut responses = Vec::new();
+    for socket in sockets {
+        let stream = match UnixStream::connect(&socket).await {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                let _ = std::fs::remove_file(&socket);
+                continue;
+            }
+            Err(_) => continue,
+        };
+        let response = send_request(stream, request).await;
+        responses.push((socket, response));
+    }
+    responses
+}
+
+/// Sends `request` to the servers with the given PIDs without waiting for
+/// their responses, giving up on any that does not accept it quickly.
+pub async fn notify(pids: &[u32], request: &ControlRequest) {
+    let Ok(mut json) = serde_json::to_string(request) else {
+        return;
+    };
+    json.push('\n');
+
+    for socket in pids.iter().filter_map(|pid| socket_path_of(*pid)) {
+        let send = async {
+            let mut stream = UnixStream::connect(socket).await?;
+            stream.write_all(json.as_bytes()).await
+        };
+        let _ = tokio::time::timeout(NOTIFY_TIMEOUT, send).await;
+    }
+}
+
+async fn send_request(
+    stream: UnixStream,
+    request: &ControlRequest,
+) -> std::io::Result<ControlResponse> {
+    let (reader, mut writer) = stream.into_split();
+    let mut json = serde_json::to_string(request)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    Ok(serde_json::from_str(&line)?)
+}
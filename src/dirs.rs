@@ -0,0 +1,151 @@
+use std::env::home_dir;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const CONFIG_ENV_VAR: &str = "DISCORD_LSP_PRESENCE_CONFIG";
+
+/// Config file given with `--config`, which takes precedence over
+/// [`CONFIG_ENV_VAR`].
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Runtime dir, resolved once as the fallback is created and checked.
+static RUNTIME_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Resolves an XDG base directory from `var`, falling back to `default`
+/// under the home directory. Relative values are ignored, as the spec asks.
+fn xdg_dir(var: &str, default: &[&str]) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home_dir().map(|home| default.iter().fold(home, |dir, part| dir.join(part))))
+}
+
+pub fn get_config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", &[".config"]).map(|dir| dir.join("discord-presence-lsp"))
+}
+
+pub fn get_state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", &[".local", "state"]).map(|dir| dir.join("discord-lsp-presence"))
+}
+
+pub fn get_data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", &[".local", "share"]).map(|dir| dir.join("discord-lsp-presence"))
+}
+
+/// Returns the dir for control sockets and instance state, under
+/// `$XDG_RUNTIME_DIR` or else a private per-user dir in the temp dir.
+pub fn get_runtime_dir() -> Option<PathBuf> {
+    RUNTIME_DIR
+        .get_or_init(|| {
+            std::env::var_os("XDG_RUNTIME_DIR")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .map(|dir| dir.join("discord-lsp-presence"))
+                .or_else(private_temp_dir)
+        })
+        .clone()
+}
+
+/// Creates `discord-lsp-presence-<uid>` in the temp dir, readable by the
+/// current user only. The temp dir is shared, so a dir of that name that
+/// belongs to someone else is refused.
+#[cfg(unix)]
+fn private_temp_dir() -> Option<PathBuf> {
+    use std::fs::{DirBuilder, Permissions};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    // SAFETY: `geteuid` has no preconditions and cannot fail.
+    let uid = unsafe { libc::geteuid() };
+    let dir = std::env::temp_dir().join(format!("discord-lsp-presence-{}", uid));
+    let _ = DirBuilder::new().mode(0o700).create(&dir);
+
+    let metadata = std::fs::symlink_metadata(&dir).ok()?;
+    if !metadata.is_dir() || metadata.uid() != uid {
+        return None;
+    }
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(&dir, Permissions::from_mode(0o700)).ok()?;
+    }
+    Some(dir)
+}
+
+#[cfg(not(unix))]
+fn private_temp_dir() -> Option<PathBuf> {
+    Some(std::env::temp_dir().join("discord-lsp-presence"))
+}
+
+/// Returns the config file: the `--config` flag, then
+/// `$DISCORD_LSP_PRESENCE_CONFIG`, then `config.toml` in the config dir.
+pub fn get_config_path() -> Option<PathBuf> {
+    if let Some(path) = CONFIG_PATH.get() {
+        return Some(path.clone());
+    }
+    std::env::var_os(CONFIG_ENV_VAR)
+        .filter(|path| !path.is_empty())
+        .map(|path| absolute(Path::new(&path)))
+        .or_else(|| get_config_dir().map(|dir| dir.join("config.toml")))
+}
+
+/// Removes `--config <PATH>` or `--config=<PATH>` from `args`, ignoring
+/// anything after `--`, and uses that file as the config file.
+pub fn take_config_flag(args: &mut Vec<String>) -> Result<(), String> {
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let Some(i) = args[..end]
+        .iter()
+        .position(|arg| arg == "--config" || arg.starts_with("--config="))
+    else {
+        return Ok(());
+    };
+
+    let flag = args.remove(i);
+    let path = match flag.strip_prefix("--config=") {
+        Some(path) => path.to_string(),
+        None if i < end - 1 => args.remove(i),
+        None => return Err("--config requires a path".to_string()),
+    };
+    if path.is_empty() {
+        return Err("--config requires a path".to_string());
+    }
+    let _ = CONFIG_PATH.set(absolute(Path::new(&path)));
+    Ok(())
+}
+
+/// Makes `path` absolute so it can be compared with document URIs.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Entry point of `discord-lsp-presence paths`, which prints where the
+/// config, logs and runtime files are looked for.
+pub fn run_paths() -> i32 {
+    let show =
+        |dir: Option<PathBuf>| dir.map_or("(unknown)".to_string(), |dir| dir.display().to_string());
+    println!("config file: {}", show(get_config_path()));
+    println!("config dir:  {}", show(get_config_dir()));
+    println!("state dir:   {}", show(get_state_dir()));
+    println!("data dir:    {}", show(get_data_dir()));
+    println!("runtime dir: {}", show(get_runtime_dir()));
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn config_flag_is_removed_before_the_separator_only() {
+        let mut wrap = args(&["wrap", "--", "server", "--config", "x"]);
+        take_config_flag(&mut wrap).unwrap();
+        assert_eq!(wrap, args(&["wrap", "--", "server", "--config", "x"]));
+
+        let mut missing = args(&["--config"]);
+        assert!(take_config_flag(&mut missing).is_err());
+    }
+}
@@ -1,60 +1,397 @@
-use crate::config::Config;
-use crate::language::LanguageInfo;
 use discord_presence::Client as DiscordClient;
+use discord_presence::DiscordError;
+use discord_presence::models::EventData;
+use discord_presence::models::rich_presence::Activity;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tower_lsp::Client;
-use tower_lsp::lsp_types::MessageType;
-
-pub async fn update_presence(
-    discord: &Arc<Mutex<DiscordClient>>,
-    client: &Client,
-    config: &Config,
-    filename: &str,
-    workspace: &str,
-    language: &LanguageInfo,
-    start_timestamp: Option<u64>,
-) {
-    let mut discord = discord.lock().await;
-    let activity = config.build_activity(filename, workspace, language, start_timestamp);
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, watch};
+use tokio::time::Instant;
+
+use crate::logging::Logger;
+
+/// Quiet period after a change before it is sent, so bursts of edits coalesce.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Discord silently drops activity updates sent more often than this.
+const RATE_LIMIT: Duration = Duration::from_secs(15);
+/// How long to wait for the IPC thread to stop on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where activities end up: the Discord client, or a recording in tests.
+pub trait PresenceSink: Send + 'static {
+    /// Starts connecting in the background.
+    fn start(&mut self);
+    fn is_ready(&self) -> bool;
+    fn set_activity(&mut self, activity: Activity) -> Result<(), DiscordError>;
+    fn clear_activity(&mut self) -> Result<(), DiscordError>;
+    /// Closes the connection, blocking until it is closed.
+    fn shutdown(self: Box<Self>) -> Result<(), DiscordError>;
+}
+
+pub type SharedSink = Arc<Mutex<Box<dyn PresenceSink>>>;
+
+impl PresenceSink for DiscordClient {
+    fn start(&mut self) {
+        DiscordClient::start(self);
+    }
+
+    fn is_ready(&self) -> bool {
+        DiscordClient::is_ready()
+    }
+
+    fn set_activity(&mut self, activity: Activity) -> Result<(), DiscordError> {
+        DiscordClient::set_activity(self, |_| activity).map(|_| ())
+    }
+
+    fn clear_activity(&mut self) -> Result<(), DiscordError> {
+        DiscordClient::clear_activity(self).map(|_| ())
+    }
+
+    fn shutdown(self: Box<Self>) -> Result<(), DiscordError> {
+        DiscordClient::shutdown(*self)
+    }
+}
+
+/// Coalesces presence changes into as few Discord updates as possible.
+///
+/// Callers set the desired activity (or `None` to clear it) as often as they
+/// like; a background task sends the latest value once it has settled,
+/// skips values identical to what Discord already shows, and spaces updates
+/// by [`RATE_LIMIT`] so the final state is never dropped. Clears are sent
+/// without waiting, and failed updates are retried after [`RATE_LIMIT`].
+#[derive(Clone)]
+pub struct PresencePublisher {
+    desired: Arc<watch::Sender<Option<Activity>>>,
+    force: Arc<AtomicBool>,
+    closed: Arc<AtomicBool>,
+}
+
+pub struct PublisherTask {
+    desired: watch::Receiver<Option<Activity>>,
+    force: Arc<AtomicBool>,
+    closed: Arc<AtomicBool>,
+}
+
+impl PresencePublisher {
+    pub fn new() -> (Self, PublisherTask) {
+        let (tx, rx) = watch::channel(None);
+        let force = Arc::new(AtomicBool::new(false));
+        let closed = Arc::new(AtomicBool::new(false));
+        let publisher = Self {
+            desired: Arc::new(tx),
+            force: Arc::clone(&force),
+            closed: Arc::clone(&closed),
+        };
+        let task = PublisherTask {
+            desired: rx,
+            force,
+            closed,
+        };
+        (publisher, task)
+    }
+
+    pub fn publish(&self, activity: Activity) {
+        self.desired.send_replace(Some(activity));
+    }
+
+    pub fn clear(&self) {
+        self.desired.send_replace(None);
+    }
+
+    /// Sends the desired state again even if it was already sent, e.g. after
+    /// Discord reconnected.
+    pub fn resend(&self) {
+        self.force.store(true, Ordering::Relaxed);
+        self.desired.send_modify(|_| {});
+    }
+
+    /// Stops publishing, returning whether the publisher was still open.
+    fn close(&self) -> bool {
+        let was_open = !self.closed.swap(true, Ordering::Relaxed);
+        self.desired.send_modify(|_| {});
+        was_open
+    }
+}
+
+impl PublisherTask {
+    pub fn spawn(self, discord: SharedSink, logger: Logger) {
+        tokio::spawn(self.run(discord, logger));
+    }
+
+    async fn run(mut self, discord: SharedSink, logger: Logger) {
+        let mut last_sent: Option<Activity> = None;
+        let mut last_sent_at: Option<Instant> = None;
+        // When to try again after Discord failed to take the desired state.
+        let mut retry_at: Option<Instant> = None;
+
+        loop {
+            let changed = match retry_at {
+                Some(at) => tokio::select! {
+                    changed = self.desired.changed() => changed,
+                    _ = tokio::time::sleep_until(at) => Ok(()),
+                },
+                None => self.desired.changed().await,
+            };
+            if changed.is_err() || self.closed.load(Ordering::Relaxed) {
+                break;
+            }
+
+            tokio::time::sleep(DEBOUNCE).await;
+            // Clears skip the rate limit so that hidden presence disappears
+            // at once, even if they end up spacing the next update further.
+            if let Some(at) = last_sent_at
+                && self.desired.borrow().is_some()
+            {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(at + RATE_LIMIT) => {}
+                    _ = self.desired.wait_for(Option::is_none) => {}
+                }
+            }
+
+            if self.closed.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let desired = self.desired.borrow_and_update().clone();
+            let forced = self.force.swap(false, Ordering::Relaxed);
+            retry_at = None;
+            if (desired == last_sent && !forced) || !discord.lock().await.is_ready() {
+                continue;
+            }
+
+            let sent = match &desired {
+                Some(activity) => update_presence(&discord, &logger, activity.clone()).await,
+                None => clear_presence(&discord, &logger).await,
+            };
+            if sent {
+                last_sent = desired;
+                last_sent_at = Some(Instant::now());
+            } else {
+                retry_at = Some(Instant::now() + RATE_LIMIT);
+            }
+        }
+    }
+}
+
+/// Creates a client for `application_id` that resends the desired activity
+/// whenever Discord (re)connects. The client still has to be started.
+pub fn new_client(
+    application_id: u64,
+    publisher: &PresencePublisher,
+    logger: &Logger,
+) -> DiscordClient {
+    let client = DiscordClient::new(application_id);
+    // Whether the current run of failed connection attempts was reported.
+    let reported = Arc::new(AtomicBool::new(false));
 
-    match discord.set_activity(|_| activity) {
+    {
+        let publisher = publisher.clone();
+        let logger = logger.clone();
+        let reported = Arc::clone(&reported);
+        client
+            .on_ready(move |_ctx| {
+                reported.store(false, Ordering::Relaxed);
+                logger.info("Discord client ready.");
+                publisher.resend();
+            })
+            .persist();
+    }
+
+    // Connection errors are only logged, never fatal: in wrap mode this
+    // process also relays the editor's real language server, which must
+    // keep running without Discord. The client retries on its own.
+    let logger = logger.clone();
+    client
+        .on_error(move |ctx| {
+            let message = match ctx.event {
+                EventData::Error(error) => error.message.unwrap_or_default(),
+                _ => String::new(),
+            };
+            if reported.swap(true, Ordering::Relaxed) {
+                logger.debug(format!("Discord connection error: {}", message));
+            } else {
+                logger.warn(format!("Could not connect to Discord: {}", message));
+            }
+        })
+        .persist();
+
+    client
+}
+
+/// Runs `f` with the sink on a blocking thread, as the Discord client blocks
+/// until Discord replies.
+async fn with_sink<T: Send + 'static>(
+    discord: &SharedSink,
+    f: impl FnOnce(&mut dyn PresenceSink) -> Result<T, DiscordError> + Send + 'static,
+) -> Result<T, String> {
+    let mut sink = Arc::clone(discord).lock_owned().await;
+    match tokio::task::spawn_blocking(move || f(&mut **sink)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn update_presence(
+    discord: &SharedSink,
+    logger: &Logger,
+    activity: Activity,
+) -> bool {
+    let details = activity.details.clone().unwrap_or_default();
+    let state = activity.state.clone().unwrap_or_default();
+
+    match with_sink(discord, move |sink| sink.set_activity(activity)).await {
         Ok(_) => {
-            let (details, state) = config.build_details_and_state(filename, workspace, language);
-            client
-                .log_message(
-                    MessageType::INFO,
-                    &format!("Set activity to: {} {}", details, state),
-                )
-                .await;
+            logger.debug(format!("Set activity to: {} {}", details, state));
+            true
         }
         Err(e) => {
-            client
-                .log_message(
-                    MessageType::ERROR,
-                    &format!("Failed to set activity: {}", e),
-                )
-                .await;
+            logger.error(format!("Failed to set activity: {}", e));
+            false
         }
     }
 }
 
-pub async fn clear_presence(
-    discord: &Arc<Mutex<DiscordClient>>,
-    client: &Client,
-) {
-    let mut discord = discord.lock().await;
-
-    match discord.clear_activity() {
+async fn clear_presence(
+    discord: &SharedSink,
+    logger: &Logger,
+) -> bool {
+    match with_sink(discord, |sink| sink.clear_activity()).await {
         Ok(_) => {
-            client
-                .log_message(MessageType::INFO, "Discord presence cleared.")
-                .await;
+            logger.debug("Discord presence cleared.");
+            true
         }
         Err(e) => {
-            client
-                .log_message(MessageType::ERROR, &format!("Failed to clear presence: {}", e))
-                .await;
+            logger.error(format!("Failed to clear presence: {}", e));
+            false
+        }
+    }
+}
+
+/// Stops the publisher, clears the activity immediately (bypassing the rate
+/// limit) and closes the IPC connection, giving up after
+/// [`SHUTDOWN_TIMEOUT`] if Discord stalls. Safe to call more than once.
+pub async fn shutdown(
+    discord: &SharedSink,
+    publisher: &PresencePublisher,
+    logger: &Logger,
+) {
+    if !publisher.close() {
+        return;
+    }
+
+    let stopped = async {
+        let old = std::mem::replace(
+            &mut *discord.lock().await,
+            Box::new(DiscordClient::new(0)) as Box<dyn PresenceSink>,
+        );
+        tokio::task::spawn_blocking(move || {
+            let mut old = old;
+            let cleared = old.is_ready().then(|| old.clear_activity());
+            (cleared, old.shutdown())
+        })
+        .await
+    };
+    let Ok(Ok((cleared, closed))) = tokio::time::timeout(SHUTDOWN_TIMEOUT, stopped).await else {
+        logger.warn("Timed out closing Discord connection.");
+        return;
+    };
+
+    match cleared {
+        Some(Ok(())) => logger.debug("Discord presence cleared."),
+        Some(Err(e)) => logger.error(format!("Failed to clear presence: {}", e)),
+        None => {}
+    }
+    match closed {
+        Ok(()) => logger.debug("Discord connection closed."),
+        Err(DiscordError::NotStarted) => {}
+        Err(e) => logger.warn(format!("Failed to close Discord connection: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use tokio::sync::mpsc;
+
+    /// Records when each activity was sent, `None` for a clear, failing
+    /// while `failures` is above zero.
+    struct TimedSink {
+        sent: mpsc::UnboundedSender<(Instant, Option<String>)>,
+        failures: Arc<AtomicU32>,
+    }
+
+    impl TimedSink {
+        fn record(&mut self, details: Option<String>) -> Result<(), DiscordError> {
+            let failing = self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_ok();
+            if failing {
+                return Err(DiscordError::NotStarted);
+            }
+            let _ = self.sent.send((Instant::now(), details));
+            Ok(())
+        }
+    }
+
+    impl PresenceSink for TimedSink {
+        fn start(&mut self) {}
+
+        fn is_ready(&self) -> bool {
+            true
+        }
+
+        fn set_activity(&mut self, activity: Activity) -> Result<(), DiscordError> {
+            self.record(Some(activity.details.unwrap_or_default()))
+        }
+
+        fn clear_activity(&mut self) -> Result<(), DiscordError> {
+            self.record(None)
+        }
+
+        fn shutdown(self: Box<Self>) -> Result<(), DiscordError> {
+            Ok(())
         }
     }
+
+    fn activity(details: &str) -> Activity {
+        Activity::new().details(details)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesces_spaces_and_retries_updates() {
+        let (tx, mut sent) = mpsc::unbounded_channel();
+        let failures = Arc::new(AtomicU32::new(0));
+        let sink = TimedSink {
+            sent: tx,
+            failures: Arc::clone(&failures),
+        };
+        let discord: SharedSink = Arc::new(Mutex::new(Box::new(sink)));
+        let (publisher, task) = PresencePublisher::new();
+        task.spawn(discord, Logger::without_file(None));
+        let start = Instant::now();
+
+        publisher.publish(activity("first"));
+        publisher.publish(activity("second"));
+        let (second_at, details) = sent.recv().await.unwrap();
+        assert_eq!(details.as_deref(), Some("second"));
+        assert_eq!(second_at - start, DEBOUNCE);
+
+        publisher.publish(activity("third"));
+        let (third_at, details) = sent.recv().await.unwrap();
+        assert_eq!(details.as_deref(), Some("third"));
+        assert_eq!(third_at - second_at, RATE_LIMIT);
+
+        publisher.clear();
+        let (cleared_at, details) = sent.recv().await.unwrap();
+        assert_eq!(details, None);
+        assert_eq!(cleared_at - third_at, DEBOUNCE);
+
+        failures.store(1, Ordering::Relaxed);
+        publisher.publish(activity("fourth"));
+        let (fourth_at, details) = sent.recv().await.unwrap();
+        assert_eq!(details.as_deref(), Some("fourth"));
+        assert_eq!(fourth_at - cleared_at, RATE_LIMIT * 2 + DEBOUNCE);
+    }
 }
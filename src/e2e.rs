@@ -0,0 +1,270 @@
+use discord_presence::DiscordError;
+use discord_presence::models::rich_presence::Activity;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader, DuplexStream};
+use tokio::sync::{Mutex, mpsc};
+use tower_lsp::Server;
+
+use crate::ServerParts;
+use crate::clock::ManualClock;
+use crate::config::Config;
+use crate::discord::{PresencePublisher, PresenceSink, SharedSink};
+use crate::logging::Logger;
+use crate::proxy::read_message;
+
+const MAIN_RS: &str = "file:///nonexistent/app/main.rs";
+const LIB_RS: &str = "file:///nonexistent/app/lib.rs";
+const START: u64 = 1_700_000_000;
+
+/// Records what would have been sent to Discord, `None` for a clear.
+struct RecordingSink {
+    sent: mpsc::UnboundedSender<Option<Activity>>,
+}
+
+impl PresenceSink for RecordingSink {
+    fn start(&mut self) {}
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    fn set_activity(&mut self, activity: Activity) -> Result<(), DiscordError> {
+        let _ = self.sent.send(Some(activity));
+        Ok(())
+    }
+
+    fn clear_activity(&mut self) -> Result<(), DiscordError> {
+        let _ = self.sent.send(None);
+        Ok(())
+    }
+
+    fn shutdown(self: Box<Self>) -> Result<(), DiscordError> {
+        Ok(())
+    }
+}
+
+/// A server driven over in-memory streams like an editor would, publishing
+/// to a [`RecordingSink`].
+///
+/// Time tracking, pauses and status expiry follow a [`ManualClock`], while
+/// tokio's clock is paused so the debounce and rate limit elapse as soon as
+/// nothing else is left to do.
+struct Harness {
+    clock: ManualClock,
+    input: DuplexStream,
+    messages: mpsc::UnboundedReceiver<Value>,
+    sent: mpsc::UnboundedReceiver<Option<Activity>>,
+    next_id: i64,
+}
+
+impl Harness {
+    /// Starts and initializes a server whose config file holds `settings`.
+    async fn start(settings: Value) -> Self {
+        let config = Config::from_settings(&settings, &Value::Null).unwrap();
+        let logger = Logger::without_file(config.log_level);
+        let (publisher, publisher_task) = PresencePublisher::new();
+        let (sent_tx, sent) = mpsc::unbounded_channel();
+        let discord: SharedSink = Arc::new(Mutex::new(Box::new(RecordingSink { sent: sent_tx })));
+        let clock = ManualClock::new(START);
+        let parts = ServerParts {
+            config,
+            file_settings: Arc::new(settings),
+            logger,
+            publisher,
+            publisher_task,
+            discord,
+            clock: Arc::new(clock.clone()),
+            instances: None,
+            control_socket: false,
+        };
+        let (service, socket, _) = parts.build(false);
+
+        let (input, server_input) = tokio::io::duplex(64 * 1024);
+        let (server_output, output) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(Server::new(server_input, server_output, socket).serve(service));
+
+        let (messages_tx, messages) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut output = BufReader::new(output);
+            while let Ok(Some(message)) = read_message(&mut output).await {
+                let Ok(value) = serde_json::from_slice(message.body()) else {
+                    continue;
+                };
+                if messages_tx.send(value).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut harness = Self {
+            clock,
+            input,
+            messages,
+            sent,
+            next_id: 0,
+        };
+        let params = json!({ "capabilities": {}, "rootUri": "file:///nonexistent/app" });
+        harness.request("initialize", params).await;
+        harness.notify("initialized", json!({})).await;
+        harness
+    }
+
+    async fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        let header = format!("Content-Length: {}\r\n\r\n", body.len());
+        self.input.write_all(header.as_bytes()).await.unwrap();
+        self.input.write_all(body.as_bytes()).await.unwrap();
+    }
+
+    async fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .await;
+    }
+
+    /// Sends a request and returns its result, skipping the notifications
+    /// sent in the meantime.
+    async fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await;
+        loop {
+            let message = self.messages.recv().await.expect("server stopped");
+            if message["id"] == id && message.get("method").is_none() {
+                return message["result"].clone();
+            }
+        }
+    }
+
+    async fn execute(&mut self, command: &str, arguments: Value) -> Value {
+        let params = json!({ "command": command, "arguments": arguments });
+        self.request("workspace/executeCommand", params).await
+    }
+
+    async fn open(&mut self, uri: &str) {
+        let document = json!({ "uri": uri, "languageId": "rust", "version": 1, "text": "" });
+        self.notify("textDocument/didOpen", json!({ "textDocument": document }))
+            .await;
+    }
+
+    async fn change(&mut self, uri: &str) {
+        let params = json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": "fn main() {}" }],
+        });
+        self.notify("textDocument/didChange", params).await;
+    }
+
+    /// Returns the next activity sent to Discord, `None` when presence was
+    /// cleared.
+    async fn next_activity(&mut self) -> Option<Activity> {
+        self.sent.recv().await.expect("publisher stopped")
+    }
+
+    async fn next_details(&mut self) -> Option<String> {
+        let activity = self.next_activity().await;
+        activity.map(|activity| activity.details.unwrap_or_default())
+    }
+
+    /// Returns the timer start of the next activity sent to Discord.
+    async fn next_timer_start(&mut self) -> Option<u64> {
+        let activity = self.next_activity().await?;
+        activity.timestamps.and_then(|timestamps| timestamps.start)
+    }
+}
+
+fn settings() -> Value {
+    json!({
+        "activity": {
+            "viewing": { "details": "Viewing {filename}" },
+            "editing": { "details": "Editing {filename}" },
+        },
+    })
+}
+
+#[tokio::test(start_paused = true)]
+async fn presence_follows_documents_and_commands() {
+    let mut editor = Harness::start(settings()).await;
+
+    editor.open(MAIN_RS).await;
+    assert_eq!(
+        editor.next_details().await.as_deref(),
+        Some("Viewing main.rs")
+    );
+    editor.change(MAIN_RS).await;
+    assert_eq!(
+        editor.next_details().await.as_deref(),
+        Some("Editing main.rs")
+    );
+
+    editor.execute("discord-presence.disable", json!([])).await;
+    assert_eq!(editor.next_details().await, None);
+    let status = editor.execute("discord-presence.status", json!([])).await;
+    assert_eq!(status["enabled"], false);
+    assert_eq!(status["discordConnected"], true);
+    assert_eq!(status["filename"], "main.rs");
+    assert_eq!(status["workspace"], "app");
+
+    editor.execute("discord-presence.toggle", json!([])).await;
+    assert_eq!(
+        editor.next_details().await.as_deref(),
+        Some("Editing main.rs")
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn pause_and_custom_status_expire() {
+    let mut editor = Harness::start(settings()).await;
+    editor.open(MAIN_RS).await;
+    assert_eq!(
+        editor.next_details().await.as_deref(),
+        Some("Viewing main.rs")
+    );
+
+    editor
+        .execute("discord-presence.pause", json!(["10m"]))
+        .await;
+    assert_eq!(editor.next_details().await, None);
+    let status = editor.execute("discord-presence.status", json!([])).await;
+    assert_eq!(status["resumeAt"], START + 600);
+    editor.clock.advance(Duration::from_secs(600));
+    assert_eq!(
+        editor.next_details().await.as_deref(),
+        Some("Viewing main.rs")
+    );
+
+    let status = json!([{ "message": "In a meeting", "expires": "30m" }]);
+    editor.execute("discord-presence.set-status", status).await;
+    assert_eq!(editor.next_details().await.as_deref(), Some("In a meeting"));
+    editor.clock.advance(Duration::from_secs(1800));
+    assert_eq!(
+        editor.next_details().await.as_deref(),
+        Some("Viewing main.rs")
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn timer_follows_time_tracking() {
+    let mut editor = Harness::start(json!({ "time_tracking": "workspace" })).await;
+    editor.open(MAIN_RS).await;
+    assert_eq!(editor.next_timer_start().await, Some(START));
+
+    editor.clock.advance(Duration::from_secs(300));
+    editor.open(LIB_RS).await;
+    editor.change(LIB_RS).await;
+    let status = editor.execute("discord-presence.status", json!([])).await;
+    assert_eq!(status["filename"], "lib.rs");
+    assert_eq!(status["timerStart"], START);
+
+    let settings = json!({ "discord-presence": { "time_tracking": "file" } });
+    editor
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": settings }),
+        )
+        .await;
+    let status = editor.execute("discord-presence.status", json!([])).await;
+    assert_eq!(status["timerStart"], START + 300);
+}
@@ -0,0 +1,290 @@
+use std::path::Path;
+
+use crate::dirs::get_config_path;
+
+pub const INIT_USAGE: &str =
+    "Usage: discord-lsp-presence init [--force] [--editor helix|neovim|kakoune]";
+
+/// Written by `init`: every option, commented out, with its default or an
+/// example value.
+const CONFIG_TEMPLATE: &str = r#"# discord-lsp-presence configuration.
+#
+# Every option is commented out and shows its default or an example. Run
+# `discord-lsp-presence check-config` after editing, or open this file with
+# the server attached for diagnostics and completion.
+
+# Discord application whose uploaded assets the images refer to.
+# application_id = 1470506076574187745
+
+# Reset the timer when switching files ("file") or only when switching
+# workspaces ("workspace").
+# time_tracking = "file"
+
+# Name used by the {editor} placeholder.
+# editor_name = "Helix"
+
+# Start with presence turned on.
+# enabled = true
+
+# "error", "warn", "info", "debug" or "trace"; $DISCORD_LSP_LOG overrides it.
+# log_level = "info"
+
+# Answer hover and similar requests (with nothing) to learn which document
+# is focused.
+# focus_hints = true
+
+# Profile used when none is selected and no profile matches the workspace.
+# profile = "work"
+
+# Text fields accept {filename}, {workspace}, {language}, {editor},
+# {repository}, {errors} and {warnings}.
+# [activity]
+# details = "Editing: {filename}"
+# state = "in {workspace}"
+# large_image_key = "helix"
+# large_image_text = "Helix Editor"
+# Preferred over large_image_key and large_image_text when set.
+# editor_image_key = "helix"
+# editor_image_text = "{editor}"
+# Show the language icon as the small image.
+# language_images = true
+# Add a "View repository" button linking the workspace's repository.
+# repository_button = false
+# "playing", "listening", "watching" or "competing".
+# activity_type = "playing"
+
+# Up to two buttons in total, including the repository button.
+# [[activity.buttons]]
+# label = "My website"
+# url = "https://example.com"
+
+# Show the party size next to the activity, counting open documents
+# ("documents") or editors working on the same workspace ("instances").
+# [activity.party]
+# source = "documents"
+# max = 10
+
+# Shown while no document is open.
+# [activity.empty]
+# details = "Idle in {editor}"
+# state = "in {workspace}"
+# large_image_key = "helix"
+# large_image_text = "Helix Editor"
+
+# Used while a document has no unsaved changes.
+# [activity.viewing]
+# details = "Viewing: {filename}"
+# state = "in {workspace}"
+# small_image_key = "viewing"
+# small_image_text = "Reading"
+
+# Used while a document has unsaved changes.
+# [activity.editing]
+# details = "Editing: {filename}"
+# state = "in {workspace}"
+# small_image_key = "editing"
+# small_image_text = "Writing"
+
+# [privacy]
+# Never link any repository.
+# private = false
+# Workspace names (globs allowed) whose repository is never linked.
+# blocklist = ["secret-*"]
+
+# Turn presence off on a recurring schedule.
+# [[schedule.quiet_hours]]
+# days = ["mon", "tue", "wed", "thu", "fri"]
+# start = "09:00"
+# end = "17:30"
+
+# Profiles replace the activity, privacy and time_tracking settings above
+# while in use. Workspaces are names or paths, globs allowed.
+# [profiles.work]
+# workspaces = ["~/work/*"]
+# time_tracking = "workspace"
+# [profiles.work.activity]
+# details = "Working"
+# [profiles.work.privacy]
+# private = true
+"#;
+
+/// Helix languages and their default language servers, which have to be
+/// listed again when adding this one.
+const HELIX_LANGUAGES: &[(&str, &[&str])] = &[
+    ("rust", &["rust-analyzer"]),
+    ("c", &["clangd"]),
+    ("cpp", &["clangd"]),
+    ("go", &["gopls", "golangci-lint-lsp"]),
+    ("python", &["ruff", "jedi", "pylsp"]),
+    ("javascript", &["typescript-language-server"]),
+    ("typescript", &["typescript-language-server"]),
+    ("jsx", &["typescript-language-server"]),
+    ("tsx", &["typescript-language-server"]),
+    ("java", &["jdtls"]),
+    ("lua", &["lua-language-server"]),
+    ("zig", &["zls"]),
+    ("bash", &["bash-language-server"]),
+    ("html", &["vscode-html-language-server", "superhtml"]),
+    ("css", &["vscode-css-language-server"]),
+    ("json", &["vscode-json-language-server"]),
+    ("yaml", &["yaml-language-server", "ansible-language-server"]),
+    ("toml", &["taplo"]),
+    ("markdown", &["marksman", "markdown-oxide"]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Editor {
+    Helix,
+    Neovim,
+    Kakoune,
+}
+
+impl Editor {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "helix" | "hx" => Some(Self::Helix),
+            "neovim" | "nvim" => Some(Self::Neovim),
+            "kakoune" | "kak" => Some(Self::Kakoune),
+            _ => None,
+        }
+    }
+
+    /// Returns the configuration registering `command` as a language server
+    /// for every language.
+    fn snippet(self, command: &str) -> String {
+        match self {
+            Self::Helix => {
+                let mut snippet = format!(
+                    "# ~/.config/helix/languages.toml\n\
+                     [language-server.discord-presence]\n\
+                     command = \"{}\"\n\
+                     \n\
+                     # Each language keeps its default servers, which may differ in your Helix\n\
+                     # version: check them with `hx --health <language>`.\n",
+                    command
+                );
+                for (name, servers) in HELIX_LANGUAGES {
+                    let servers: Vec<String> = servers
+                        .iter()
+                        .chain(&["discord-presence"])
+                        .map(|server| format!("\"{}\"", server))
+                        .collect();
+                    snippet.push_str(&format!(
+                        "\n[[language]]\nname = \"{}\"\nlanguage-servers = [{}]\n",
+                        name,
+                        servers.join(", ")
+                    ));
+                }
+                snippet
+            }
+            Self::Neovim => format!(
+                "-- init.lua (Neovim 0.11 or later); without filetypes it attaches everywhere\n\
+                 vim.lsp.config('discord_presence', {{\n  \
+                   cmd = {{ '{}' }},\n  \
+                   root_markers = {{ '.git' }},\n\
+                 }})\n\
+                 vim.lsp.enable('discord_presence')\n",
+                command
+            ),
+            Self::Kakoune => format!(
+                "# kakrc, after `lsp-enable` (kakoune-lsp 17 or later)\n\
+                 hook -group discord-presence global BufSetOption filetype=.* %{{\n    \
+                   set-option -add buffer lsp_servers %{{\n        \
+                     [discord-presence]\n        \
+                     root_globs = [\".git\"]\n        \
+                     command = \"{}\"\n    \
+                   }}\n\
+                 }}\n",
+                command
+            ),
+        }
+    }
+}
+
+/// Entry point of `discord-lsp-presence init`, which writes a commented
+/// config file and prints how to register the server with an editor.
+/// Returns the process exit code.
+pub fn run_init(args: &[String]) -> i32 {
+    let mut force = false;
+    let mut editor = Editor::Helix;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let name = match arg.as_str() {
+            "--force" => {
+                force = true;
+                continue;
+            }
+            "--editor" => rest.next().map(String::as_str),
+            arg => arg.strip_prefix("--editor="),
+        };
+        match name.and_then(Editor::parse) {
+            Some(parsed) => editor = parsed,
+            None => {
+                eprintln!("{}", INIT_USAGE);
+                return 2;
+            }
+        }
+    }
+
+    let Some(path) = get_config_path() else {
+        eprintln!("Could not determine the config file location.");
+        return 2;
+    };
+    if path.exists() && !force {
+        eprintln!(
+            "{} already exists; pass --force to overwrite it.",
+            path.display()
+        );
+        return 1;
+    }
+    if let Err(e) = write_config(&path) {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+        return 1;
+    }
+    println!("Wrote {}", path.display());
+
+    let command = std::env::current_exe()
+        .map(|exe| exe.display().to_string())
+        .unwrap_or_else(|_| "discord-lsp-presence".to_string());
+    println!();
+    print!("{}", editor.snippet(&command));
+    0
+}
+
+fn write_config(path: &Path) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, CONFIG_TEMPLATE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::{FieldKind, KNOWN_KEYS, check};
+
+    #[test]
+    fn template_documents_every_option() {
+        let uncommented: Vec<&str> = CONFIG_TEMPLATE
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(option) if option.starts_with('[') || option.contains(" = ") => option,
+                _ => line,
+            })
+            .collect();
+        let uncommented = uncommented.join("\n");
+
+        assert_eq!(check(&uncommented), []);
+        for (key, kind) in KNOWN_KEYS {
+            if matches!(kind, FieldKind::Table | FieldKind::ArrayOfTables) {
+                continue;
+            }
+            let name = key.rsplit('.').next().unwrap();
+            assert!(
+                uncommented.contains(&format!("\n{} = ", name)),
+                "`{}` is missing from the template",
+                key
+            );
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dirs::get_runtime_dir;
+
+/// State shared by every server instance spawned by the same editor process.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct SharedState {
+    active_pid: Option<u32>,
+    #[serde(default)]
+    instances: HashMap<u32, u64>,
+    workspace: Option<String>,
+    workspace_start: Option<u64>,
+}
+
+/// Coordinates sibling instances so that only the most recently active one
+/// publishes presence and the workspace timer is shared between them.
+///
+/// Editors like Helix start one server per language, all children of the
+/// same editor process, so siblings are grouped by parent PID.
+///
+/// The shared state lives in a locked file, so every method except
+/// [`InstanceCoordinator::is_active`] blocks on file I/O.
+pub struct InstanceCoordinator {
+    pid: u32,
+    dir: PathBuf,
+    /// Whether this instance was the active one when last checked.
+    active: AtomicBool,
+}
+
+impl InstanceCoordinator {
+    pub fn new() -> Option<Self> {
+        let parent = parent_pid()?;
+        let dir = get_runtime_dir()?.join(parent.to_string());
+        fs::create_dir_all(&dir).ok()?;
+
+        let coordinator = Self {
+            pid: std::process::id(),
+            dir,
+            active: AtomicBool::new(false),
+        };
+        coordinator.with_state(|state, pid| {
+            state.instances.entry(pid).or_insert(0);
+        });
+        coordinator.refresh_active();
+        Some(coordinator)
+    }
+
+    /// Marks this instance as the active one and returns the shared start
+    /// timestamp for `workspace`, resetting it if the workspace changed.
+    pub fn claim(&self, workspace: &str) -> Option<u64> {
+        self.active.store(true, Ordering::Relaxed);
+        self.with_state(|state, pid| {
+            let now = now_timestamp();
+            state.active_pid = Some(pid);
+            state.instances.insert(pid, now);
+
+            if state.workspace.as_deref() != Some(workspace) || state.workspace_start.is_none() {
+                state.workspace = Some(workspace.to_string());
+                state.workspace_start = Some(now);
+            }
+            state.workspace_start
+        })
+        .flatten()
+    }
+
+    /// Returns whether this instance should be publishing presence, as of
+    /// the last [`InstanceCoordinator::claim`] or
+    /// [`InstanceCoordinator::refresh_active`].
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Rereads which instance is the active one, returning whether
+    /// [`InstanceCoordinator::is_active`] changed.
+    ///
+    /// If the active instance went away, the most recently active surviving
+    /// sibling takes over; before any document was opened, the oldest
+    /// instance is the active one.
+    pub fn refresh_active(&self) -> bool {
+        let active = self
+            .with_state(|state, pid| {
+                if state.active_pid.is_none() {
+                    state.active_pid = state
+                        .instances
+                        .iter()
+                        .max_by_key(|(pid, last_active)| (**last_active, Reverse(**pid)))
+                        .map(|(pid, _)| *pid);
+                }
+                state.active_pid == Some(pid)
+            })
+            .unwrap_or(true);
+        self.active.swap(active, Ordering::Relaxed) != active
+    }
+
+    /// Counts the other editor processes whose instances are alive, by the
+    /// workspace they are currently working on.
+    pub fn count_other_editors(&self) -> HashMap<String, u32> {
+        let mut counts = HashMap::new();
+        let Some(Ok(entries)) = get_runtime_dir().map(fs::read_dir) else {
+            return counts;
+        };
+
+        let workspaces = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|dir| *dir != self.dir && dir.is_dir())
+            .filter_map(|dir| fs::read_to_string(dir.join("state.json")).ok())
+            .filter_map(|json| serde_json::from_str::<SharedState>(&json).ok())
+            .filter(|state| state.instances.keys().any(|pid| is_alive(*pid)))
+            .filter_map(|state| state.workspace);
+        for workspace in workspaces {
+            *counts.entry(workspace).or_insert(0u32) += 1;
+        }
+        counts
+    }
+
+    /// Returns the PIDs of the presence servers started by the same editor
+    /// process as this one, without registering this process.
+    pub fn siblings() -> Vec<u32> {
+        let Some(parent) = parent_pid() else {
+            return Vec::new();
+        };
+        let Some(dir) = get_runtime_dir() else {
+            return Vec::new();
+        };
+        let path = dir.join(parent.to_string()).join("state.json");
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<SharedState>(&json).ok())
+            .map(|state| state.instances.into_keys().collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes this instance, deleting the shared directory once the last
+    /// sibling is gone.
+    pub fn unregister(&self) {
+        let last = self.with_state(|state, pid| {
+            state.instances.remove(&pid);
+            if state.active_pid == Some(pid) {
+                state.active_pid = None;
+            }
+            state.instances.is_empty()
+        });
+        if last == Some(true) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn with_state<T>(&self, f: impl FnOnce(&mut SharedState, u32) -> T) -> Option<T> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join("state.lock"))
+            .ok()?;
+        lock.lock().ok()?;
+
+        let path = self.dir.join("state.json");
+        let stored: SharedState = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+
+        let mut state = stored.clone();
+        state.instances.retain(|pid, _| is_alive(*pid));
+        if state.active_pid.is_some_and(|pid| !state.instances.contains_key(&pid)) {
+            state.active_pid = None;
+        }
+
+        let result = f(&mut state, self.pid);
+
+        let tmp = self.dir.join(format!("state.json.{}", self.pid));
+        if state != stored
+            && let Ok(json) = serde_json::to_string(&state)
+            && fs::write(&tmp, json).is_ok()
+        {
+            let _ = fs::rename(&tmp, &path);
+        }
+
+        let _ = File::unlock(&lock);
+        Some(result)
+    }
+}
+
+#[cfg(unix)]
+fn parent_pid() -> Option<u32> {
+    Some(std::os::unix::process::parent_id())
+}
+
+#[cfg(not(unix))]
+fn parent_pid() -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 performs only the existence and permission checks.
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+    true
+}
+
+fn now_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
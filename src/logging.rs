@@ -0,0 +1,332 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tower_lsp::Client;
+use tower_lsp::lsp_types::notification::LogTrace;
+use tower_lsp::lsp_types::{LogTraceParams, MessageType, TraceValue};
+
+use crate::dirs::get_state_dir;
+
+pub const LOG_ENV_VAR: &str = "DISCORD_LSP_LOG";
+const LOG_FILE_NAME: &str = "discord-lsp-presence.log";
+const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+const MAX_ROTATED_FILES: usize = 3;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" | "warning" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        }
+    }
+
+    fn message_type(self) -> MessageType {
+        match self {
+            Self::Error => MessageType::ERROR,
+            Self::Warn => MessageType::WARNING,
+            Self::Info => MessageType::INFO,
+            Self::Debug | Self::Trace => MessageType::LOG,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Error,
+            1 => Self::Warn,
+            2 => Self::Info,
+            3 => Self::Debug,
+            _ => Self::Trace,
+        }
+    }
+}
+
+/// What the writer task receives: a message to log or a flush request.
+enum Record {
+    Message(LogLevel, String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Sends log records to a rotating file in the state directory and to the
+/// LSP client.
+///
+/// Records at or below the configured level go to both; when the client
+/// enabled tracing with `$/setTrace`, more verbose records are additionally
+/// forwarded as `$/logTrace` notifications.
+#[derive(Clone)]
+pub struct Logger {
+    level: Arc<AtomicU8>,
+    trace: Arc<AtomicU8>,
+    tx: mpsc::UnboundedSender<Record>,
+    client: Arc<OnceLock<Client>>,
+}
+
+impl Logger {
+    /// Creates a logger whose level comes from `$DISCORD_LSP_LOG`, falling
+    /// back to `configured`, and starts its writer task.
+    pub fn new(configured: Option<LogLevel>) -> Self {
+        Self::start(configured, LogFile::open())
+    }
+
+    /// Creates a logger that writes no log file.
+    #[cfg(test)]
+    pub fn without_file(configured: Option<LogLevel>) -> Self {
+        Self::start(configured, None)
+    }
+
+    fn start(configured: Option<LogLevel>, file: Option<LogFile>) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let logger = Self {
+            level: Arc::new(AtomicU8::new(resolve_level(configured) as u8)),
+            trace: Arc::new(AtomicU8::new(trace_to_u8(TraceValue::Off))),
+            tx,
+            client: Arc::new(OnceLock::new()),
+        };
+        tokio::spawn(logger.clone().run(rx, file));
+        logger
+    }
+
+    pub fn attach_client(&self, client: Client) {
+        let _ = self.client.set(client);
+    }
+
+    /// Applies a newly configured level, unless `$DISCORD_LSP_LOG` overrides it.
+    pub fn set_configured_level(&self, configured: Option<LogLevel>) {
+        self.level.store(resolve_level(configured) as u8, Ordering::Relaxed);
+    }
+
+    pub fn set_trace(&self, trace: TraceValue) {
+        self.trace.store(trace_to_u8(trace), Ordering::Relaxed);
+    }
+
+    pub fn level(&self) -> LogLevel {
+        LogLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    fn trace_level(&self) -> Option<LogLevel> {
+        match self.trace.load(Ordering::Relaxed) {
+            0 => None,
+            1 => Some(LogLevel::Debug),
+            _ => Some(LogLevel::Trace),
+        }
+    }
+
+    pub fn log(&self, level: LogLevel, message: impl Into<String>) {
+        let enabled = level <= self.level() || self.trace_level().is_some_and(|t| level <= t);
+        if enabled {
+            let _ = self.tx.send(Record::Message(level, message.into()));
+        }
+    }
+
+    pub fn error(&self, message: impl Into<String>) {
+        self.log(LogLevel::Error, message);
+    }
+
+    pub fn warn(&self, message: impl Into<String>) {
+        self.log(LogLevel::Warn, message);
+    }
+
+    pub fn info(&self, message: impl Into<String>) {
+        self.log(LogLevel::Info, message);
+    }
+
+    pub fn debug(&self, message: impl Into<String>) {
+        self.log(LogLevel::Debug, message);
+    }
+
+    pub fn trace(&self, message: impl Into<String>) {
+        self.log(LogLevel::Trace, message);
+    }
+
+    /// Waits until every record logged so far has been written out.
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send(Record::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<Record>, mut file: Option<LogFile>) {
+        while let Some(record) = rx.recv().await {
+            let (level, message) = match record {
+                Record::Message(level, message) => (level, message),
+                Record::Flush(done) => {
+                    let _ = done.send(());
+                    continue;
+                }
+            };
+
+            if let Some(file) = file.as_mut() {
+                file.write(level, &message);
+            }
+
+            let Some(client) = self.client.get() else {
+                if level <= LogLevel::Warn {
+                    eprintln!("{}: {}", level.as_str(), message);
+                }
+                continue;
+            };
+
+            if level <= self.level() {
+                client.log_message(level.message_type(), &message).await;
+            } else if self.trace_level().is_some() {
+                client
+                    .send_notification::<LogTrace>(LogTraceParams {
+                        message: format!("[{}] {}", level.as_str(), message),
+                        verbose: None,
+                    })
+                    .await;
+            }
+        }
+    }
+}
+
+/// `$DISCORD_LSP_LOG` takes precedence over the configured level.
+fn resolve_level(configured: Option<LogLevel>) -> LogLevel {
+    std::env::var(LOG_ENV_VAR)
+        .ok()
+        .and_then(|value| LogLevel::parse(&value))
+        .or(configured)
+        .unwrap_or_default()
+}
+
+fn trace_to_u8(trace: TraceValue) -> u8 {
+    match trace {
+        TraceValue::Off => 0,
+        TraceValue::Messages => 1,
+        TraceValue::Verbose => 2,
+    }
+}
+
+/// Log file that rotates to `.1`, `.2`, ... once it grows past
+/// [`MAX_LOG_FILE_SIZE`].
+///
+/// Sibling servers append to the same file, so its size is read from the
+/// path rather than counted, and a file rotated by another process is
+/// replaced by the new one before writing.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl LogFile {
+    fn open() -> Option<Self> {
+        let dir = get_state_dir()?;
+        fs::create_dir_all(&dir).ok()?;
+        let path = dir.join(LOG_FILE_NAME);
+        let file = OpenOptions::new().create(true).append(true).open(&path).ok()?;
+        Some(Self { path, file })
+    }
+
+    fn write(&mut self, level: LogLevel, message: &str) {
+        if self.current_size() >= MAX_LOG_FILE_SIZE {
+            self.rotate();
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let line = format!(
+            "{} [{}] {} {}\n",
+            timestamp,
+            std::process::id(),
+            level.as_str(),
+            message
+        );
+        let _ = self.file.write_all(line.as_bytes());
+    }
+
+    /// Returns the size of the file at the path, switching to it first if
+    /// it is no longer the open one.
+    fn current_size(&mut self) -> u64 {
+        let open = self.file.metadata().ok();
+        match fs::metadata(&self.path) {
+            Ok(current) if open.is_some_and(|open| is_same_file(&open, &current)) => current.len(),
+            _ => {
+                self.reopen();
+                self.file.metadata().map_or(0, |m| m.len())
+            }
+        }
+    }
+
+    fn reopen(&mut self) {
+        if let Ok(file) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+        {
+            self.file = file;
+        }
+    }
+
+    /// Rotates the files while holding a lock, unless a sibling server
+    /// rotated them first.
+    fn rotate(&mut self) {
+        let Ok(lock) = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("log.lock"))
+        else {
+            return;
+        };
+        if lock.lock().is_err() {
+            return;
+        }
+
+        if self.current_size() >= MAX_LOG_FILE_SIZE {
+            let rotated = |n: usize| self.path.with_extension(format!("log.{}", n));
+            for n in (1..MAX_ROTATED_FILES).rev() {
+                let _ = fs::rename(rotated(n), rotated(n + 1));
+            }
+            let _ = fs::rename(&self.path, rotated(1));
+            self.reopen();
+        }
+
+        let _ = File::unlock(&lock);
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// Open files cannot be renamed elsewhere, so the open file is still the
+/// one at the path.
+#[cfg(not(unix))]
+fn is_same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
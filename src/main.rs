@@ -1,51 +1,185 @@
-use discord_presence::Client as DiscordClient;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService, Server};
 use url::Url;
 
+mod check;
+mod clock;
 mod config;
+mod config_lsp;
+#[cfg(unix)]
+mod control;
+mod dirs;
 mod discord;
+#[cfg(test)]
+mod e2e;
+mod init;
+mod instance;
 mod language;
+mod logging;
+mod presence;
+mod proxy;
+mod schedule;
 mod state;
+mod status;
 mod workspace;
 
-use config::{Config, TimeTracking};
-use language::detect_language;
-use state::{FileState, WorkspaceState};
-use workspace::{detect_workspace_name, get_filename_from_uri};
-
+use clock::{SharedClock, SystemClock};
+use config::Config;
+use discord::{PresencePublisher, PublisherTask, SharedSink};
+use instance::InstanceCoordinator;
+use logging::Logger;
+use presence::{Presence, PresenceEvent, PresenceState};
+use state::{CustomStatus, DiagnosticCounts, IdleState};
+use status::StatusReport;
+use workspace::{detect_root_repository_url, detect_root_workspace_name};
+
+#[derive(Clone)]
 struct Backend {
     client: Client,
-    discord: Arc<Mutex<DiscordClient>>,
-    config: Arc<Config>,
-    current_file: Arc<Mutex<Option<FileState>>>,
-    current_workspace: Arc<Mutex<Option<WorkspaceState>>>,
-    enabled: Arc<Mutex<bool>>,
+    logger: Logger,
+    discord: SharedSink,
+    publisher: PresencePublisher,
+    /// The config file merged with the editor's settings.
+    config: Arc<Mutex<Arc<Config>>>,
+    /// The config file's own settings, which the editor's are merged over.
+    file_settings: Arc<serde_json::Value>,
+    presence: Presence,
+    clock: SharedClock,
+    instances: Option<Arc<InstanceCoordinator>>,
+    /// Whether to listen on the control socket.
+    control_socket: bool,
+    /// Contents of this server's own config file while it is open.
+    config_document: Arc<Mutex<Option<String>>>,
+    teardown: Teardown,
+}
+
+/// Releases everything the server holds outside the process, whichever way
+/// it stops: `shutdown`, `exit`, stdin EOF or a termination signal.
+#[derive(Clone)]
+struct Teardown {
+    discord: SharedSink,
+    publisher: PresencePublisher,
+    instances: Option<Arc<InstanceCoordinator>>,
+    logger: Logger,
+}
+
+impl Teardown {
+    async fn run(&self) {
+        discord::shutdown(&self.discord, &self.publisher, &self.logger).await;
+        if let Some(instances) = &self.instances {
+            instances.unregister();
+        }
+        #[cfg(unix)]
+        control::remove_socket();
+        self.logger.flush().await;
+    }
+}
+
+#[cfg(unix)]
+async fn termination_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = sigterm.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn termination_signal() {
+    let _ = tokio::signal::ctrl_c().await;
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        if let Some(trace) = params.trace {
+            self.logger.set_trace(trace);
+        }
+
+        let root = params
+            .workspace_folders
+            .as_ref()
+            .and_then(|folders| folders.first())
+            .map(|folder| folder.uri.clone())
+            .or(params.root_uri);
+        let workspace = root
+            .as_ref()
+            .and_then(detect_root_workspace_name)
+            .unwrap_or_else(|| "unknown workspace".to_string());
+        let repository = root.as_ref().and_then(detect_root_repository_url);
+        let path = root.as_ref().and_then(|root| root.to_file_path().ok());
+        let idle = IdleState::new(workspace, repository, path, &*self.clock);
+        self.presence.send(PresenceEvent::Root(idle));
+
+        if let Some(options) = &params.initialization_options {
+            let previous = self.apply_settings(options).await;
+            let application_id = self.config().await.get_application_id();
+            if application_id != previous.get_application_id() {
+                *self.discord.lock().await = Box::new(discord::new_client(
+                    application_id,
+                    &self.publisher,
+                    &self.logger,
+                ));
+            }
+        }
+
+        let focus_hints = self.config().await.use_focus_hints();
         Ok(InitializeResult {
             server_info: Some(ServerInfo {
                 name: "discord-lsp-presence".to_string(),
                 version: Some("0.1.0".to_string()),
             }),
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::FULL),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    },
                 )),
                 execute_command_provider: Some(ExecuteCommandOptions {
                     commands: vec![
                         "discord-presence.enable".to_string(),
                         "discord-presence.disable".to_string(),
                         "discord-presence.toggle".to_string(),
+                        "discord-presence.pause".to_string(),
+                        "discord-presence.set-status".to_string(),
+                        "discord-presence.profile".to_string(),
+                        "discord-presence.status".to_string(),
                     ],
                     ..Default::default()
                 }),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec!["{".to_string(), "[".to_string()]),
+                    ..Default::default()
+                }),
+                hover_provider: focus_hints.then_some(HoverProviderCapability::Simple(true)),
+                document_highlight_provider: focus_hints.then_some(OneOf::Left(true)),
+                code_lens_provider: focus_hints.then_some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                inlay_hint_provider: focus_hints.then_some(OneOf::Left(true)),
+                semantic_tokens_provider: focus_hints.then(|| {
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                        legend: SemanticTokensLegend::default(),
+                        full: Some(SemanticTokensFullOptions::Bool(true)),
+                        ..Default::default()
+                    })
+                }),
                 ..Default::default()
             },
         })
@@ -54,106 +188,182 @@ impl LanguageServer for Backend {
     async fn initialized(&self, _: InitializedParams) {
         let mut discord = self.discord.lock().await;
         discord.start();
+        drop(discord);
+
+        self.logger.info("Discord client started.");
+        self.presence.send(PresenceEvent::Refresh);
+
+        if self.instances.is_some() {
+            let backend = self.clone();
+            tokio::spawn(async move { backend.watch_siblings().await });
+        }
+
+        {
+            let backend = self.clone();
+            tokio::spawn(async move { backend.watch_schedule().await });
+        }
 
-        self.client
-            .log_message(MessageType::INFO, "Discord client started.")
-            .await;
+        #[cfg(unix)]
+        if self.control_socket {
+            let backend = self.clone();
+            let logger = self.logger.clone();
+            tokio::spawn(async move {
+                let handler = move |request| {
+                    let backend = backend.clone();
+                    async move { backend.handle_control(request).await }
+                };
+                if let Err(e) = control::serve(handler).await {
+                    logger.warn(format!("Control socket unavailable: {}", e));
+                }
+            });
+        }
     }
 
     async fn shutdown(&self) -> Result<()> {
+        self.teardown.run().await;
         Ok(())
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        self.client
-            .log_message(MessageType::INFO, "Opened file")
-            .await;
+        self.logger.debug(format!("Opened {}", params.text_document.uri));
+
+        let uri = params.text_document.uri;
+        if config_lsp::is_config_file(&uri) {
+            let text = params.text_document.text;
+            self.check_config_document(uri.clone(), text).await;
+        }
+        self.presence.send(PresenceEvent::Opened(uri));
+    }
 
-        self.handle_file_event(&params.text_document.uri).await;
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        if config_lsp::is_config_file(&params.text_document.uri) {
+            *self.config_document.lock().await = None;
+            self.client
+                .publish_diagnostics(params.text_document.uri.clone(), Vec::new(), None)
+                .await;
+        }
+        self.presence
+            .send(PresenceEvent::Closed(params.text_document.uri));
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        self.client
-            .log_message(MessageType::INFO, "File changed")
-            .await;
+        self.logger.trace(format!("Changed {}", params.text_document.uri));
+
+        let uri = params.text_document.uri;
+        if config_lsp::is_config_file(&uri)
+            && let Some(change) = params.content_changes.into_iter().last()
+            && change.range.is_none()
+        {
+            self.check_config_document(uri.clone(), change.text).await;
+        }
+        self.presence.send(PresenceEvent::Changed(uri));
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        self.logger.trace(format!("Saved {}", params.text_document.uri));
+
+        self.presence
+            .send(PresenceEvent::Saved(params.text_document.uri));
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let previous = self.apply_settings(&params.settings).await;
+        if self.config().await.get_application_id() != previous.get_application_id() {
+            self.logger
+                .warn("The application ID changes once the server is restarted.");
+        }
+        self.logger.info("Configuration updated.");
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let position = params.text_document_position;
+        if !config_lsp::is_config_file(&position.text_document.uri) {
+            return Ok(None);
+        }
+        let config_document = self.config_document.lock().await;
+        let Some(text) = config_document.as_deref() else {
+            return Ok(None);
+        };
+        let items = config_lsp::completions(text, position.position);
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        self.focus(&uri).await;
+        Ok(None)
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        self.focus(&uri).await;
+        Ok(None)
+    }
 
-        self.handle_file_event(&params.text_document.uri).await;
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        self.focus(&params.text_document.uri).await;
+        Ok(None)
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        self.focus(&params.text_document.uri).await;
+        Ok(None)
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        self.focus(&params.text_document.uri).await;
+        Ok(None)
     }
 
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
         let command = params.command.as_str();
 
         match command {
-            "discord-presence.enable" => {
-                let mut enabled = self.enabled.lock().await;
-                if !*enabled {
-                    *enabled = true;
-                    self.client.log_message(MessageType::INFO, "Discord presence enabled.").await;
-
-                    if let Some(file_state) = self.current_file.lock().await.as_ref() {
-                        let language = detect_language(&file_state.filename);
-                        let ts = match self.config.get_time_tracking() {
-                            TimeTracking::File => file_state.get_start_timestamp(),
-                            TimeTracking::Workspace => self.current_workspace.lock().await
-                                .as_ref()
-                                .map(|ws| ws.get_start_timestamp())
-                                .unwrap_or_else(|| file_state.get_start_timestamp()),
-                        };
-                        discord::update_presence(
-                            &self.discord,
-                            &self.client,
-                            &self.config,
-                            &file_state.filename,
-                            &file_state.workspace,
-                            &language,
-                            Some(ts),
-                        ).await;
-                    }
-                } else {
-                    self.client.log_message(MessageType::INFO, "Discord presence is already enabled.").await;
+            "discord-presence.enable" => self.presence.send(PresenceEvent::Enabled(true)),
+            "discord-presence.disable" => self.presence.send(PresenceEvent::Enabled(false)),
+            "discord-presence.toggle" => self.presence.send(PresenceEvent::Toggle),
+            "discord-presence.pause" => {
+                let duration = params.arguments.first().and_then(|arg| match arg {
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    serde_json::Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                });
+                if let Err(message) = self.pause(duration.as_deref()).await {
+                    self.client.show_message(MessageType::ERROR, &message).await;
                 }
             }
-            "discord-presence.disable" => {
-                let mut enabled = self.enabled.lock().await;
-                if *enabled {
-                    *enabled = false;
-                    self.client.log_message(MessageType::INFO, "Discord presence disabled.").await;
-                    discord::clear_presence(&self.discord, &self.client).await;
-                } else {
-                    self.client.log_message(MessageType::INFO, "Discord presence is already disabled.").await;
+            "discord-presence.set-status" => {
+                let (message, small_image, expires) = match params.arguments.as_slice() {
+                    [serde_json::Value::Object(args)] => {
+                        let field = |name: &str| args.get(name).and_then(|v| v.as_str());
+                        (field("message"), field("small_image"), field("expires"))
+                    }
+                    args => {
+                        let arg = |i: usize| args.get(i).and_then(|v| v.as_str());
+                        (arg(0), arg(1), arg(2))
+                    }
+                };
+                let small_image = small_image.filter(|key| !key.is_empty());
+                if let Err(message) = self.set_custom_status(message, small_image, expires).await {
+                    self.client.show_message(MessageType::ERROR, &message).await;
                 }
             }
-            "discord-presence.toggle" => {
-                let mut enabled = self.enabled.lock().await;
-                *enabled = !*enabled;
-
-                if *enabled {
-                    self.client.log_message(MessageType::INFO, "Discord presence enabled.").await;
-
-                    if let Some(file_state) = self.current_file.lock().await.as_ref() {
-                        let language = detect_language(&file_state.filename);
-                        let ts = match self.config.get_time_tracking() {
-                            TimeTracking::File => file_state.get_start_timestamp(),
-                            TimeTracking::Workspace => self.current_workspace.lock().await
-                                .as_ref()
-                                .map(|ws| ws.get_start_timestamp())
-                                .unwrap_or_else(|| file_state.get_start_timestamp()),
-                        };
-                        discord::update_presence(
-                            &self.discord,
-                            &self.client,
-                            &self.config,
-                            &file_state.filename,
-                            &file_state.workspace,
-                            &language,
-                            Some(ts),
-                        ).await;
-                    }
-                } else {
-                    self.client.log_message(MessageType::INFO, "Discord presence disabled.").await;
-                    discord::clear_presence(&self.discord, &self.client).await;
+            "discord-presence.profile" => {
+                let name = params.arguments.first().and_then(|arg| arg.as_str());
+                if let Err(message) = self.set_profile(name).await {
+                    self.client.show_message(MessageType::ERROR, &message).await;
                 }
             }
+            "discord-presence.status" => {
+                let status = self.status_report().await;
+                return Ok(serde_json::to_value(status).ok());
+            }
             _ => {}
         }
 
@@ -162,128 +372,414 @@ impl LanguageServer for Backend {
 }
 
 impl Backend {
-    async fn handle_file_event(&self, uri: &Url) {
-        if !*self.enabled.lock().await {
-            return;
+    /// Disables presence, resuming automatically once `duration` (e.g. `30m`)
+    /// has elapsed if one is given.
+    async fn pause(&self, duration: Option<&str>) -> std::result::Result<(), String> {
+        let Some(duration) = duration else {
+            self.presence.send(PresenceEvent::Enabled(false));
+            return Ok(());
+        };
+        let (duration, deadline) = schedule::parse_duration(duration)
+            .and_then(|d| Some((d, self.clock.now().checked_add(d)?)))
+            .ok_or_else(|| format!("Invalid pause duration: {}", duration))?;
+
+        self.presence.send(PresenceEvent::Pause(deadline));
+        self.logger.info(format!(
+            "Discord presence paused for {}.",
+            schedule::format_duration(duration)
+        ));
+        Ok(())
+    }
+
+    /// Returns the presence status, with Discord's connection state taken
+    /// from the sink.
+    async fn status_report(&self) -> StatusReport {
+        let mut status = self.presence.status().await;
+        status.discord_connected = self.discord.lock().await.is_ready();
+        status
+    }
+
+    /// Turns presence off when entering configured quiet hours and back on
+    /// when leaving them, leaving manual changes in between untouched.
+    async fn watch_schedule(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        let mut was_quiet = false;
+
+        loop {
+            interval.tick().await;
+
+            let quiet = self
+                .config()
+                .await
+                .get_schedule()
+                .is_some_and(|schedule| schedule.is_quiet_now());
+            if quiet != was_quiet {
+                self.presence.send(PresenceEvent::QuietHours(quiet));
+                was_quiet = quiet;
+            }
         }
+    }
 
-        let filename = get_filename_from_uri(uri);
-        let workspace_name = detect_workspace_name(uri);
+    /// Replaces the templated details with `message`, or clears the custom
+    /// status when no message is given.
+    async fn set_custom_status(
+        &self,
+        message: Option<&str>,
+        small_image: Option<&str>,
+        expires: Option<&str>,
+    ) -> std::result::Result<(), String> {
+        let expires_at = match expires.filter(|e| !e.is_empty()) {
+            Some(expires) => {
+                let deadline = schedule::parse_duration(expires)
+                    .and_then(|duration| self.clock.now().checked_add(duration))
+                    .ok_or_else(|| format!("Invalid status expiry: {}", expires))?;
+                Some(deadline)
+            }
+            None => None,
+        };
+        let status = message
+            .filter(|m| !m.is_empty())
+            .map(|message| CustomStatus {
+                message: message.to_string(),
+                small_image: small_image.map(str::to_string),
+                expires_at,
+            });
+
+        let log = match &status {
+            Some(status) => format!("Custom status set to: {}", status.message),
+            None => "Custom status cleared.".to_string(),
+        };
+        self.logger.info(log);
+
+        self.presence.send(PresenceEvent::CustomStatus(status));
+        Ok(())
+    }
 
-        if let Some(filename) = filename {
-            let workspace = workspace_name.unwrap_or_else(|| "unknown workspace".to_string());
-            let language = detect_language(&filename);
+    async fn config(&self) -> Arc<Config> {
+        Arc::clone(&*self.config.lock().await)
+    }
 
-            let start_timestamp = match self.config.get_time_tracking() {
-                TimeTracking::File => {
-                    let state = FileState::new(filename.clone(), workspace.clone());
-                    let ts = state.get_start_timestamp();
-                    *self.current_file.lock().await = Some(state);
-                    Some(ts)
-                }
-                TimeTracking::Workspace => {
-                    let mut current_workspace = self.current_workspace.lock().await;
-                    let ts = match current_workspace.as_ref() {
-                        Some(ws) if ws.workspace == workspace => ws.get_start_timestamp(),
-                        _ => {
-                            let new_ws = WorkspaceState::new(workspace.clone());
-                            let ts = new_ws.get_start_timestamp();
-                            *current_workspace = Some(new_ws);
-                            ts
-                        }
-                    };
-                    Some(ts)
-                }
-            };
+    /// Selects profile `name` for every workspace, or goes back to picking
+    /// profiles by workspace when no name is given.
+    async fn set_profile(&self, name: Option<&str>) -> std::result::Result<(), String> {
+        let name = name.filter(|name| !name.is_empty());
+        if let Some(name) = name
+            && !self.config().await.has_profile(name)
+        {
+            return Err(format!("Unknown profile: {}", name));
+        }
 
-            if DiscordClient::is_ready() {
-                discord::update_presence(
-                    &self.discord,
-                    &self.client,
-                    &self.config,
-                    &filename,
-                    &workspace,
-                    &language,
-                    start_timestamp,
-                )
-                .await;
+        let log = match name {
+            Some(name) => format!("Switched to profile {}.", name),
+            None => "Picking profiles by workspace again.".to_string(),
+        };
+        self.logger.info(log);
+
+        self.presence
+            .send(PresenceEvent::Profile(name.map(str::to_string)));
+        Ok(())
+    }
+
+    /// Replaces the config with the config file merged with the editor's
+    /// `settings`, returning the previous config. Invalid settings are
+    /// reported and leave the config unchanged.
+    async fn apply_settings(&self, settings: &serde_json::Value) -> Arc<Config> {
+        let config = match Config::from_settings(&self.file_settings, settings) {
+            Ok(config) => Arc::new(config),
+            Err(e) => {
+                self.logger.warn(format!("Ignoring invalid editor settings: {}", e));
+                return self.config().await;
             }
+        };
+        let previous = std::mem::replace(&mut *self.config.lock().await, Arc::clone(&config));
+
+        self.logger.set_configured_level(config.log_level);
+        self.presence.send(PresenceEvent::Config(Arc::clone(&config)));
+        if config.is_enabled() != previous.is_enabled() {
+            self.presence
+                .send(PresenceEvent::Enabled(config.is_enabled()));
         }
+        previous
     }
-}
 
-#[tokio::main]
-async fn main() {
-    let config = Arc::new(Config::load());
+    /// Publishes the problems found in this server's own config file, which
+    /// the editor has open as `uri`.
+    async fn check_config_document(&self, uri: Url, text: String) {
+        let diagnostics = config_lsp::diagnostics(&text);
+        *self.config_document.lock().await = Some(text);
+        self.client.publish_diagnostics(uri, diagnostics, None).await;
+    }
 
-    let discord = Arc::new(Mutex::new(DiscordClient::new(config.get_application_id())));
+    /// Switches to `uri` when a request shows the editor is displaying it.
+    ///
+    /// Editors such as Helix send no focus events, but issue hover, highlight,
+    /// code lens, inlay hint and semantic token requests for the visible
+    /// buffer, so these double as focus hints.
+    async fn focus(&self, uri: &Url) {
+        self.presence.send(PresenceEvent::Focused(uri.clone()));
+    }
 
-    let current_file: Arc<Mutex<Option<FileState>>> = Arc::new(Mutex::new(None));
-    let current_workspace: Arc<Mutex<Option<WorkspaceState>>> = Arc::new(Mutex::new(None));
-    let enabled: Arc<Mutex<bool>> = Arc::new(Mutex::new(config.is_enabled()));
-    let current_file_for_ready = Arc::clone(&current_file);
-    let current_workspace_for_ready = Arc::clone(&current_workspace);
-    let discord_for_ready = Arc::clone(&discord);
-    let config_for_ready = Arc::clone(&config);
-    let enabled_for_ready = Arc::clone(&enabled);
+    #[cfg(unix)]
+    async fn handle_control(&self, request: control::ControlRequest) -> control::ControlResponse {
+        use control::ControlRequest;
 
-    {
-        let drpc = discord.lock().await;
+        match request {
+            ControlRequest::Pause { duration } => {
+                if let Err(error) = self.pause(duration.as_deref()).await {
+                    return control::ControlResponse::Err { error };
+                }
+            }
+            ControlRequest::Resume => self.presence.send(PresenceEvent::Enabled(true)),
+            ControlRequest::Toggle => self.presence.send(PresenceEvent::Toggle),
+            ControlRequest::Status => {}
+            ControlRequest::Profile { name } => {
+                if let Err(error) = self.set_profile(name.as_deref()).await {
+                    return control::ControlResponse::Err { error };
+                }
+            }
+            ControlRequest::Diagnostics {
+                uri,
+                errors,
+                warnings,
+            } => {
+                let Ok(uri) = Url::parse(&uri) else {
+                    return control::ControlResponse::Err {
+                        error: format!("Invalid document URI: {}", uri),
+                    };
+                };
+                let counts = DiagnosticCounts { errors, warnings };
+                self.presence.send(PresenceEvent::Diagnostics(uri, counts));
+            }
+            ControlRequest::SetStatus {
+                text,
+                small_image,
+                expires,
+            } => {
+                let result = self
+                    .set_custom_status(text.as_deref(), small_image.as_deref(), expires.as_deref())
+                    .await;
+                if let Err(error) = result {
+                    return control::ControlResponse::Err { error };
+                }
+            }
+        }
 
-        drpc.on_ready(move |_ctx| {
-            eprintln!("Discord client ready");
+        control::ControlResponse::Ok {
+            status: self.status_report().await,
+        }
+    }
 
-            if !*enabled_for_ready.blocking_lock() {
-                eprintln!("Discord presence is disabled, skipping initial presence.");
-                return;
+    /// Handler for the `$/setTrace` notification, which tower-lsp does not
+    /// route to [`LanguageServer`] itself.
+    async fn set_trace(&self, params: SetTraceParams) {
+        self.logger.set_trace(params.value);
+    }
+
+    /// Handler for the wrapped server's `textDocument/publishDiagnostics`,
+    /// which `wrap` mode forwards to this server.
+    async fn publish_diagnostics(&self, params: PublishDiagnosticsParams) {
+        let counts = DiagnosticCounts::from_diagnostics(&params.diagnostics);
+        self.presence
+            .send(PresenceEvent::Diagnostics(params.uri, counts));
+    }
+
+    /// Handler for the custom `discord-presence/status` request.
+    async fn status(&self) -> Result<StatusReport> {
+        Ok(self.status_report().await)
+    }
+
+    /// Clears presence when a sibling instance takes over and republishes it
+    /// when this instance becomes the active one again, and keeps the count
+    /// of other editors by workspace up to date for the party size.
+    async fn watch_siblings(&self) {
+        let Some(instances) = self.instances.clone() else {
+            return;
+        };
+        let mut interval = tokio::time::interval(Duration::from_secs(2));
+        let mut other_editors = HashMap::new();
+
+        loop {
+            interval.tick().await;
+
+            let check = Arc::clone(&instances);
+            let Ok((changed, counts)) = tokio::task::spawn_blocking(move || {
+                (check.refresh_active(), check.count_other_editors())
+            })
+            .await
+            else {
+                continue;
+            };
+            if counts != other_editors {
+                other_editors = counts.clone();
+                self.presence.send(PresenceEvent::OtherEditors(counts));
+            } else if changed {
+                self.presence.send(PresenceEvent::Refresh);
             }
+        }
+    }
+}
 
-            if let Some(file_state) = current_file_for_ready.blocking_lock().as_ref() {
-                eprintln!("Setting initial presence for: {}", file_state.filename);
-                let ts = match config_for_ready.get_time_tracking() {
-                    TimeTracking::File => file_state.get_start_timestamp(),
-                    TimeTracking::Workspace => current_workspace_for_ready
-                        .blocking_lock()
-                        .as_ref()
-                        .map(|ws| ws.get_start_timestamp())
-                        .unwrap_or_else(|| file_state.get_start_timestamp()),
-                };
-                let language = detect_language(&file_state.filename);
-                let activity = config_for_ready.build_activity(
-                    &file_state.filename,
-                    &file_state.workspace,
-                    &language,
-                    Some(ts),
-                );
-                let mut client = discord_for_ready.blocking_lock();
-                if let Err(e) = client.set_activity(|_| activity) {
-                    eprintln!("Failed to set initial activity: {}", e);
-                }
+/// What a server is built from, which tests replace with fakes.
+struct ServerParts {
+    config: Config,
+    file_settings: Arc<serde_json::Value>,
+    logger: Logger,
+    publisher: PresencePublisher,
+    publisher_task: PublisherTask,
+    discord: SharedSink,
+    clock: SharedClock,
+    instances: Option<Arc<InstanceCoordinator>>,
+    control_socket: bool,
+}
+
+impl ServerParts {
+    /// Starts the presence and publisher tasks and builds the LSP service,
+    /// which also handles the wrapped server's diagnostics when `wrapped`.
+    fn build(self, wrapped: bool) -> (LspService<Backend>, ClientSocket, Teardown) {
+        let Self {
+            config,
+            file_settings,
+            logger,
+            publisher,
+            publisher_task,
+            discord,
+            clock,
+            instances,
+            control_socket,
+        } = self;
+
+        publisher_task.spawn(Arc::clone(&discord), logger.clone());
+        let config = Arc::new(config);
+        let (presence, presence_task) = Presence::new(
+            PresenceState::new(Arc::clone(&config), instances.clone(), Arc::clone(&clock)),
+            publisher.clone(),
+            logger.clone(),
+        );
+        presence_task.spawn();
+        let teardown = Teardown {
+            discord: Arc::clone(&discord),
+            publisher: publisher.clone(),
+            instances: instances.clone(),
+            logger: logger.clone(),
+        };
+        let teardown_for_backend = teardown.clone();
+
+        let mut builder = LspService::build(move |client| {
+            logger.attach_client(client.clone());
+            Backend {
+                client,
+                logger,
+                discord,
+                publisher,
+                config: Arc::new(Mutex::new(config)),
+                file_settings,
+                presence,
+                clock,
+                instances,
+                control_socket,
+                config_document: Arc::new(Mutex::new(None)),
+                teardown: teardown_for_backend,
             }
         })
-        .persist();
+        .custom_method("discord-presence/status", Backend::status)
+        .custom_method("$/setTrace", Backend::set_trace);
+        if wrapped {
+            builder = builder.custom_method(
+                "textDocument/publishDiagnostics",
+                Backend::publish_diagnostics,
+            );
+        }
+        let (service, socket) = builder.finish();
+        (service, socket, teardown)
+    }
+}
 
-        drpc.on_error(|_ctx| {
-            eprintln!("Discord connection error. Exiting.");
-            std::process::exit(1);
-        })
-        .persist();
-    }
-
-    let current_file_clone = Arc::clone(&current_file);
-    let current_workspace_clone = Arc::clone(&current_workspace);
-    let config_clone = Arc::clone(&config);
-    let enabled_clone = Arc::clone(&enabled);
-    let (service, socket) = LspService::new(move |client| Backend {
-        client,
-        discord: Arc::clone(&discord),
-        config: Arc::clone(&config_clone),
-        current_file: Arc::clone(&current_file_clone),
-        current_workspace: Arc::clone(&current_workspace_clone),
-        enabled: Arc::clone(&enabled_clone),
-    });
+#[tokio::main]
+async fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = dirs::take_config_flag(&mut args) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    #[cfg(unix)]
+    if args.first().map(String::as_str) == Some("ctl") {
+        std::process::exit(control::run_ctl(&args[1..]).await);
+    }
+    if args.first().map(String::as_str) == Some("schema") {
+        std::process::exit(config::run_schema());
+    }
+    if args.first().map(String::as_str) == Some("init") {
+        std::process::exit(init::run_init(&args[1..]));
+    }
+    if args.first().map(String::as_str) == Some("paths") {
+        std::process::exit(dirs::run_paths());
+    }
+    if args.first().map(String::as_str) == Some("check-config") {
+        std::process::exit(check::run_check_config(&args[1..]));
+    }
+    if args.first().map(String::as_str) == Some("proxy") {
+        std::process::exit(proxy::run_proxy(&args[1..]).await);
+    }
+    let wrapped = match args.first().map(String::as_str) {
+        Some("wrap") => match proxy::parse_command(&args[1..]) {
+            Some(command) => Some(command),
+            None => {
+                eprintln!("{}", proxy::WRAP_USAGE);
+                std::process::exit(2);
+            }
+        },
+        _ => None,
+    };
+
+    let file_settings = Arc::new(config::load_file_settings());
+    let config =
+        Config::from_settings(&file_settings, &serde_json::Value::Null).unwrap_or_default();
+
+    let logger = Logger::new(config.log_level);
+    let (publisher, publisher_task) = PresencePublisher::new();
+    let discord: SharedSink = Arc::new(Mutex::new(Box::new(discord::new_client(
+        config.get_application_id(),
+        &publisher,
+        &logger,
+    ))));
+    let parts = ServerParts {
+        config,
+        file_settings,
+        logger,
+        publisher,
+        publisher_task,
+        discord,
+        clock: Arc::new(SystemClock),
+        instances: InstanceCoordinator::new().map(Arc::new),
+        control_socket: true,
+    };
+    let (service, socket, teardown) = parts.build(wrapped.is_some());
+
+    if let Some(command) = wrapped {
+        tokio::select! {
+            code = proxy::run_wrap(command, service, socket) => {
+                teardown.run().await;
+                std::process::exit(code);
+            }
+            _ = termination_signal() => {
+                teardown.logger.info("Received termination signal, shutting down.");
+                teardown.run().await;
+                std::process::exit(0);
+            }
+        }
+    }
 
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
-    Server::new(stdin, stdout, socket).serve(service).await;
+    tokio::select! {
+        _ = Server::new(stdin, stdout, socket).serve(service) => {
+            teardown.run().await;
+        }
+        _ = termination_signal() => {
+            teardown.logger.info("Received termination signal, shutting down.");
+            teardown.run().await;
+            // Exit explicitly: the blocking stdin reader would keep the runtime alive.
+            std::process::exit(0);
+        }
+    }
 }
@@ -0,0 +1,653 @@
+use discord_presence::models::rich_presence::{Activity, ActivityParty};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use url::Url;
+
+use crate::clock::{self, SharedClock};
+use crate::config::{ActivityContext, Config, PartySource, TimeTracking};
+use crate::discord::PresencePublisher;
+use crate::instance::InstanceCoordinator;
+use crate::language::detect_language;
+use crate::logging::Logger;
+use crate::state::{CustomStatus, DiagnosticCounts, FileState, IdleState, WorkspaceState};
+use crate::status::StatusReport;
+use crate::workspace::{detect_repository_url, detect_workspace_name, get_filename_from_uri};
+
+/// Something that may change what presence shows.
+pub enum PresenceEvent {
+    /// The workspace the editor was started in, shown until a document opens.
+    Root(IdleState),
+    Opened(Url),
+    Changed(Url),
+    Saved(Url),
+    Closed(Url),
+    /// A request showing the editor is displaying the document.
+    Focused(Url),
+    Diagnostics(Url, DiagnosticCounts),
+    Config(Arc<Config>),
+    /// Profile selected at runtime, or `None` to pick profiles by workspace.
+    Profile(Option<String>),
+    Enabled(bool),
+    Toggle,
+    /// Disables presence until the clock reaches the given deadline.
+    Pause(Duration),
+    /// Entering (`true`) or leaving configured quiet hours.
+    QuietHours(bool),
+    PauseElapsed(Duration),
+    CustomStatus(Option<CustomStatus>),
+    CustomStatusExpired(Duration),
+    /// The workspace start shared by sibling instances, once this instance
+    /// claimed it.
+    Claimed(String, u64),
+    /// Other editor processes by the workspace they are working on.
+    OtherEditors(HashMap<String, u32>),
+    /// Recomputes presence, e.g. after a sibling instance took over or left.
+    Refresh,
+    Status(oneshot::Sender<StatusReport>),
+}
+
+/// Everything presence is computed from.
+pub struct PresenceState {
+    pub config: Arc<Config>,
+    pub profile: Option<String>,
+    pub enabled: bool,
+    /// When a pause ends, as a reading of the clock.
+    pub paused_until: Option<Duration>,
+    pub current_file: Option<FileState>,
+    /// When work on the current workspace started, for `time_tracking =
+    /// "workspace"`.
+    pub current_workspace: Option<WorkspaceState>,
+    pub idle: Option<IdleState>,
+    pub custom_status: Option<CustomStatus>,
+    /// Open documents, mapped to whether they were modified since they were
+    /// opened or last saved.
+    pub open_documents: HashMap<Url, bool>,
+    /// Documents that were current, from least to most recently.
+    pub recent_documents: Vec<Url>,
+    /// Diagnostic counts reported by the wrapped server, by document.
+    pub diagnostics: HashMap<Url, DiagnosticCounts>,
+    pub instances: Option<Arc<InstanceCoordinator>>,
+    pub clock: SharedClock,
+    /// Other editor processes by workspace, for `party.source = "instances"`.
+    pub other_editors: HashMap<String, u32>,
+    /// Whether the schedule's quiet hours are in effect.
+    pub quiet_hours: bool,
+    /// Whether presence is off only because of quiet hours, so leaving them
+    /// turns it back on. Any manual change clears this.
+    pub disabled_by_schedule: bool,
+}
+
+impl PresenceState {
+    pub fn new(
+        config: Arc<Config>,
+        instances: Option<Arc<InstanceCoordinator>>,
+        clock: SharedClock,
+    ) -> Self {
+        Self {
+            enabled: config.is_enabled(),
+            config,
+            profile: None,
+            paused_until: None,
+            current_file: None,
+            current_workspace: None,
+            idle: None,
+            custom_status: None,
+            open_documents: HashMap::new(),
+            recent_documents: Vec::new(),
+            diagnostics: HashMap::new(),
+            instances,
+            clock,
+            other_editors: HashMap::new(),
+            quiet_hours: false,
+            disabled_by_schedule: false,
+        }
+    }
+
+    /// Returns the workspace shown right now, with the path its profile is
+    /// picked by: the current file's, or the idle workspace's.
+    fn location(&self) -> Option<(&str, Option<PathBuf>)> {
+        if let Some(file) = &self.current_file {
+            return Some((&file.workspace, file.uri.to_file_path().ok()));
+        }
+        self.idle
+            .as_ref()
+            .map(|idle| (idle.workspace.as_str(), idle.path.clone()))
+    }
+
+    /// Returns the profile in use for the workspace shown right now.
+    pub fn active_profile(&self) -> Option<&str> {
+        let (workspace, path) = self.location()?;
+        self.config
+            .select_profile(self.profile.as_deref(), workspace, path.as_deref())
+    }
+
+    /// Returns the config with the active profile applied.
+    pub fn current_config(&self) -> Arc<Config> {
+        match self.active_profile() {
+            Some(name) => Arc::new(self.config.with_profile(name)),
+            None => Arc::clone(&self.config),
+        }
+    }
+
+    fn is_active_instance(&self) -> bool {
+        self.instances.as_ref().is_none_or(|i| i.is_active())
+    }
+
+    /// Makes `uri` the current file, restarting the file timer when it
+    /// changes and the workspace timer when the workspace does. Returns
+    /// whether either changed.
+    fn track(&mut self, uri: &Url) -> bool {
+        let Some(filename) = get_filename_from_uri(uri) else {
+            return false;
+        };
+        let workspace =
+            detect_workspace_name(uri).unwrap_or_else(|| "unknown workspace".to_string());
+
+        let is_current = self
+            .current_file
+            .as_ref()
+            .is_some_and(|f| f.uri == *uri && f.workspace == workspace);
+        if !is_current {
+            self.recent_documents.retain(|recent| recent != uri);
+            self.recent_documents.push(uri.clone());
+            let repository = detect_repository_url(uri);
+            self.current_file = Some(FileState::new(
+                uri.clone(),
+                filename,
+                workspace.clone(),
+                repository,
+                &*self.clock,
+            ));
+        }
+
+        let same_workspace = self
+            .current_workspace
+            .as_ref()
+            .is_some_and(|ws| ws.workspace == workspace);
+        if !same_workspace {
+            self.current_workspace = Some(WorkspaceState::new(workspace, &*self.clock));
+        }
+        !is_current || !same_workspace
+    }
+
+    /// Forgets a closed document. Returns the document to show instead when
+    /// it was the current one, or shows the workspace as idle once no
+    /// document is left.
+    fn close(&mut self, uri: &Url) -> Option<Url> {
+        self.open_documents.remove(uri);
+        self.diagnostics.remove(uri);
+        self.recent_documents.retain(|recent| recent != uri);
+
+        let is_current = self.current_file.as_ref().is_some_and(|f| f.uri == *uri);
+        if is_current && let Some(next) = self.recent_documents.last() {
+            return Some(next.clone());
+        }
+        if (is_current || self.open_documents.is_empty())
+            && let Some(file) = self.current_file.take()
+        {
+            let path = file.uri.to_file_path().ok();
+            self.idle = Some(IdleState::new(
+                file.workspace,
+                file.repository,
+                path,
+                &*self.clock,
+            ));
+        }
+        None
+    }
+
+    /// Picks the timer shown for `workspace`: its own `start` or the
+    /// workspace's, depending on `time_tracking`.
+    fn start_timestamp(&self, config: &Config, workspace: &str, start: u64) -> u64 {
+        match config.get_time_tracking() {
+            TimeTracking::File => start,
+            TimeTracking::Workspace => self
+                .current_workspace
+                .as_ref()
+                .filter(|ws| ws.workspace == workspace)
+                .map_or(start, |ws| ws.get_start_timestamp(&*self.clock)),
+        }
+    }
+
+    /// Builds the activity for the current file, or the `[activity.empty]`
+    /// activity when no document is open.
+    pub fn activity(&self) -> Option<Activity> {
+        let config = self.current_config();
+        let (activity, workspace) = match &self.current_file {
+            Some(file) => {
+                let language = detect_language(&file.filename);
+                let ctx = ActivityContext {
+                    filename: &file.filename,
+                    workspace: &file.workspace,
+                    language: &language,
+                    repository: file.repository.as_deref(),
+                    editing: self.open_documents.get(&file.uri).copied().unwrap_or(false),
+                    diagnostics: self.diagnostics.get(&file.uri).copied().unwrap_or_default(),
+                };
+                let ts = self.start_timestamp(&config, &file.workspace, file.get_start_timestamp(&*self.clock));
+                (config.build_activity(&ctx, Some(ts)), &file.workspace)
+            }
+            None => {
+                let idle = self.idle.as_ref()?;
+                let ts = self.start_timestamp(&config, &idle.workspace, idle.get_start_timestamp(&*self.clock));
+                let activity = config.build_empty_activity(
+                    &idle.workspace,
+                    idle.repository.as_deref(),
+                    Some(ts),
+                )?;
+                (activity, &idle.workspace)
+            }
+        };
+        Some(self.apply_overrides(&config, activity, workspace))
+    }
+
+    /// Returns what Discord should show: nothing while disabled or while a
+    /// sibling instance is the active one.
+    pub fn desired(&self) -> Option<Activity> {
+        if !self.enabled || !self.is_active_instance() {
+            return None;
+        }
+        self.activity()
+    }
+
+    /// Applies the custom status and party size on top of a templated
+    /// activity.
+    fn apply_overrides(
+        &self,
+        config: &Config,
+        mut activity: Activity,
+        workspace: &str,
+    ) -> Activity {
+        if let Some(status) = self.custom_status.as_ref().filter(|s| !s.is_expired(&*self.clock)) {
+            activity.details = Some(status.message.clone());
+            if let Some(key) = &status.small_image {
+                let mut assets = activity.assets.unwrap_or_default();
+                assets.small_image = Some(key.clone());
+                assets.small_text = Some(status.message.clone());
+                activity.assets = Some(assets);
+            }
+        }
+
+        if let Some((size, max)) = self.party_size(config, workspace) {
+            let mut hasher = DefaultHasher::new();
+            workspace.hash(&mut hasher);
+            let id = format!("{:x}", hasher.finish());
+            activity = activity.party(|_| ActivityParty::new().id(id).size((size, max)));
+        }
+        activity
+    }
+
+    /// Returns the `(size, max)` party size when a party is configured.
+    fn party_size(&self, config: &Config, workspace: &str) -> Option<(u32, u32)> {
+        let party = config.get_party()?;
+        let size = match party.source.unwrap_or_default() {
+            PartySource::Documents => u32::try_from(self.open_documents.len()).unwrap_or(u32::MAX),
+            PartySource::Instances => self
+                .other_editors
+                .get(workspace)
+                .map_or(1, |others| others.saturating_add(1)),
+        };
+        (size > 0).then(|| (size, party.max.unwrap_or(size).max(size)))
+    }
+
+    pub fn status_report(&self) -> StatusReport {
+        let activity = self.activity();
+        let file = self.current_file.as_ref();
+        StatusReport {
+            pid: std::process::id(),
+            enabled: self.enabled,
+            resume_at: self
+                .paused_until
+                .map(|deadline| self.clock.to_unix(deadline)),
+            active: self.is_active_instance(),
+            // Filled in by the backend, which owns the sink.
+            discord_connected: false,
+            details: activity.as_ref().and_then(|a| a.details.clone()),
+            state: activity.as_ref().and_then(|a| a.state.clone()),
+            filename: file.map(|f| f.filename.clone()),
+            workspace: self.location().map(|(workspace, _)| workspace.to_string()),
+            language: file.map(|f| detect_language(&f.filename).name),
+            timer_start: activity
+                .as_ref()
+                .and_then(|a| a.timestamps.as_ref())
+                .and_then(|t| t.start),
+            custom_status: self.custom_status.as_ref().map(|s| s.message.clone()),
+            profile: self.active_profile().map(str::to_string),
+        }
+    }
+}
+
+/// Feeds events to the presence task, which owns the [`PresenceState`] and
+/// hands the resulting activity to the [`PresencePublisher`].
+#[derive(Clone)]
+pub struct Presence {
+    events: mpsc::UnboundedSender<PresenceEvent>,
+}
+
+pub struct PresenceTask {
+    state: PresenceState,
+    events: mpsc::UnboundedReceiver<PresenceEvent>,
+    /// For timers, which must not keep the task alive.
+    timers: mpsc::WeakUnboundedSender<PresenceEvent>,
+    publisher: PresencePublisher,
+    logger: Logger,
+    /// What was last handed to the publisher.
+    published: Option<Option<Activity>>,
+}
+
+impl Presence {
+    pub fn new(
+        state: PresenceState,
+        publisher: PresencePublisher,
+        logger: Logger,
+    ) -> (Self, PresenceTask) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = PresenceTask {
+            state,
+            events: rx,
+            timers: tx.downgrade(),
+            publisher,
+            logger,
+            published: None,
+        };
+        (Self { events: tx }, task)
+    }
+
+    pub fn send(&self, event: PresenceEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Returns the status once every event sent before has been handled.
+    pub async fn status(&self) -> StatusReport {
+        let (tx, rx) = oneshot::channel();
+        self.send(PresenceEvent::Status(tx));
+        rx.await.unwrap_or_default()
+    }
+}
+
+impl PresenceTask {
+    pub fn spawn(self) {
+        tokio::spawn(self.run());
+    }
+
+    async fn run(mut self) {
+        while let Some(event) = self.events.recv().await {
+            if let PresenceEvent::Status(reply) = event {
+                let _ = reply.send(self.state.status_report());
+                continue;
+            }
+            self.handle(event);
+            self.publish();
+        }
+    }
+
+    fn handle(&mut self, event: PresenceEvent) {
+        let state = &mut self.state;
+        match event {
+            PresenceEvent::Root(idle) => state.idle = Some(idle),
+            PresenceEvent::Opened(uri) => {
+                state.open_documents.insert(uri.clone(), false);
+                self.track(&uri);
+            }
+            PresenceEvent::Changed(uri) => {
+                state.open_documents.insert(uri.clone(), true);
+                self.track(&uri);
+            }
+            PresenceEvent::Saved(uri) => {
+                if let Some(modified) = state.open_documents.get_mut(&uri) {
+                    *modified = false;
+                }
+                self.track(&uri);
+            }
+            PresenceEvent::Closed(uri) => {
+                if let Some(next) = state.close(&uri) {
+                    self.track(&next);
+                }
+            }
+            PresenceEvent::Focused(uri) => {
+                let is_current = state.current_file.as_ref().is_some_and(|f| f.uri == uri);
+                if state.open_documents.contains_key(&uri) && !is_current {
+                    self.logger.debug(format!("Focused {}", uri));
+                    self.track(&uri);
+                }
+            }
+            PresenceEvent::Diagnostics(uri, counts) => {
+                state.diagnostics.insert(uri, counts);
+            }
+            PresenceEvent::Config(config) => state.config = config,
+            PresenceEvent::Profile(name) => state.profile = name,
+            PresenceEvent::Enabled(value) => {
+                state.disabled_by_schedule = false;
+                self.set_enabled(value);
+            }
+            PresenceEvent::Toggle => {
+                state.disabled_by_schedule = false;
+                let enabled = !state.enabled;
+                self.set_enabled(enabled);
+            }
+            PresenceEvent::Pause(deadline) => {
+                state.disabled_by_schedule = false;
+                self.set_enabled(false);
+                self.state.paused_until = Some(deadline);
+                self.wake_at(deadline, PresenceEvent::PauseElapsed(deadline));
+            }
+            PresenceEvent::PauseElapsed(deadline) => {
+                if state.paused_until != Some(deadline) {
+                    return;
+                }
+                if state.quiet_hours {
+                    state.paused_until = None;
+                    state.disabled_by_schedule = true;
+                } else {
+                    self.set_enabled(true);
+                }
+            }
+            PresenceEvent::QuietHours(quiet) => {
+                state.quiet_hours = quiet;
+                if quiet && state.enabled {
+                    state.disabled_by_schedule = true;
+                    self.set_enabled(false);
+                } else if !quiet && state.disabled_by_schedule {
+                    state.disabled_by_schedule = false;
+                    self.set_enabled(true);
+                }
+            }
+            PresenceEvent::CustomStatus(status) => {
+                let expires_at = status.as_ref().and_then(|s| s.expires_at);
+                state.custom_status = status;
+                if let Some(deadline) = expires_at {
+                    self.wake_at(deadline, PresenceEvent::CustomStatusExpired(deadline));
+                }
+            }
+            PresenceEvent::CustomStatusExpired(deadline) => {
+                if state.custom_status.as_ref().and_then(|s| s.expires_at) == Some(deadline) {
+                    state.custom_status = None;
+                }
+            }
+            PresenceEvent::Claimed(workspace, start) => {
+                if state.current_workspace.as_ref().is_some_and(|ws| ws.workspace == workspace) {
+                    state.current_workspace = Some(WorkspaceState::with_start_timestamp(
+                        workspace,
+                        start,
+                        &*state.clock,
+                    ));
+                }
+            }
+            PresenceEvent::OtherEditors(counts) => state.other_editors = counts,
+            PresenceEvent::Refresh | PresenceEvent::Status(_) => {}
+        }
+    }
+
+    /// Makes `uri` the current file and, when that changed it or a sibling
+    /// took over, claims the shared state for its workspace.
+    ///
+    /// Claiming locks and rewrites a file, so it runs on a blocking thread
+    /// and the shared workspace start arrives as [`PresenceEvent::Claimed`].
+    fn track(&mut self, uri: &Url) {
+        let changed = self.state.track(uri);
+        let Some(instances) = self.state.instances.clone() else {
+            return;
+        };
+        let Some(workspace) = self.state.current_file.as_ref().map(|f| f.workspace.clone()) else {
+            return;
+        };
+        if !changed && instances.is_active() {
+            return;
+        }
+
+        let timers = self.timers.clone();
+        tokio::spawn(async move {
+            let claim = tokio::task::spawn_blocking(move || {
+                match instances.claim(&workspace) {
+                    Some(start) => PresenceEvent::Claimed(workspace, start),
+                    None => PresenceEvent::Refresh,
+                }
+            });
+            if let Ok(event) = claim.await
+                && let Some(events) = timers.upgrade()
+            {
+                let _ = events.send(event);
+            }
+        });
+    }
+
+    fn set_enabled(&mut self, value: bool) {
+        if value {
+            self.state.paused_until = None;
+        }
+        if self.state.enabled == value {
+            let message = if value {
+                "Discord presence is already enabled."
+            } else {
+                "Discord presence is already disabled."
+            };
+            self.logger.debug(message);
+            return;
+        }
+        self.state.enabled = value;
+        if value {
+            self.logger.info("Discord presence enabled.");
+        } else {
+            self.logger.info("Discord presence disabled.");
+        }
+    }
+
+    /// Sends `event` to this task once the clock reaches `deadline`.
+    fn wake_at(&self, deadline: Duration, event: PresenceEvent) {
+        let timers = self.timers.clone();
+        let clock = Arc::clone(&self.state.clock);
+        tokio::spawn(async move {
+            clock::sleep_until(&*clock, deadline).await;
+            if let Some(events) = timers.upgrade() {
+                let _ = events.send(event);
+            }
+        });
+    }
+
+    /// Hands the desired activity to the publisher if it changed.
+    fn publish(&mut self) {
+        let desired = self.state.desired();
+        if self.published.as_ref() == Some(&desired) {
+            return;
+        }
+        match &desired {
+            Some(activity) => self.publisher.publish(activity.clone()),
+            None => self.publisher.clear(),
+        }
+        self.published = Some(desired);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use serde_json::json;
+
+    #[test]
+    fn builds_the_activity_from_the_current_file() {
+        let settings = json!({ "activity": { "details": "On {filename}" } });
+        let config = Config::from_settings(&settings, &serde_json::Value::Null).unwrap();
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let mut state = PresenceState::new(Arc::new(config), None, clock);
+        let uri = Url::parse("file:///tmp/project/main.rs").unwrap();
+
+        state.open_documents.insert(uri.clone(), false);
+        state.track(&uri);
+        let activity = state.desired().unwrap();
+        assert_eq!(activity.details.as_deref(), Some("On main.rs"));
+
+        state.custom_status = Some(CustomStatus {
+            message: "Reviewing".to_string(),
+            small_image: None,
+            expires_at: None,
+        });
+        assert_eq!(
+            state.desired().unwrap().details.as_deref(),
+            Some("Reviewing")
+        );
+
+        state.enabled = false;
+        assert_eq!(state.desired(), None);
+
+        state.enabled = true;
+        state.close(&uri);
+        assert_eq!(state.desired(), None);
+        assert_eq!(
+            state.idle.as_ref().map(|i| i.path.clone()),
+            Some(uri.to_file_path().ok())
+        );
+    }
+
+    #[test]
+    fn closing_the_current_document_shows_the_previous_one() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let mut state = PresenceState::new(Arc::new(Config::default()), None, clock);
+        let main = Url::parse("file:///tmp/project/main.rs").unwrap();
+        let lib = Url::parse("file:///tmp/project/lib.rs").unwrap();
+        for uri in [&main, &lib] {
+            state.open_documents.insert(uri.clone(), false);
+            state.track(uri);
+        }
+
+        assert_eq!(state.close(&lib), Some(main.clone()));
+        state.track(&main);
+        assert_eq!(state.current_file.as_ref().map(|f| &f.uri), Some(&main));
+
+        assert_eq!(state.close(&main), None);
+        assert!(state.current_file.is_none());
+        assert!(state.idle.is_some());
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_leave_manual_changes_alone() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let state = PresenceState::new(Arc::new(Config::default()), None, clock);
+        let (publisher, _) = PresencePublisher::new();
+        let (_, mut task) = Presence::new(state, publisher, Logger::without_file(None));
+
+        task.handle(PresenceEvent::QuietHours(true));
+        assert!(!task.state.enabled);
+        task.handle(PresenceEvent::QuietHours(false));
+        assert!(task.state.enabled);
+
+        // A pause taken during quiet hours outlasts them.
+        let deadline = Duration::from_secs(3600);
+        task.handle(PresenceEvent::QuietHours(true));
+        task.handle(PresenceEvent::Pause(deadline));
+        task.handle(PresenceEvent::QuietHours(false));
+        assert!(!task.state.enabled);
+        assert_eq!(task.state.paused_until, Some(deadline));
+        task.handle(PresenceEvent::PauseElapsed(deadline));
+        assert!(task.state.enabled);
+
+        task.handle(PresenceEvent::Enabled(false));
+        task.handle(PresenceEvent::QuietHours(true));
+        task.handle(PresenceEvent::QuietHours(false));
+        assert!(!task.state.enabled);
+    }
+}
@@ -0,0 +1,309 @@
+use serde::Deserialize;
+use std::process::Stdio;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::mpsc;
+use tower_lsp::{ClientSocket, LanguageServer, LspService, Server};
+
+pub const PROXY_USAGE: &str = "Usage: discord-lsp-presence proxy -- <COMMAND> [ARGS...]";
+pub const WRAP_USAGE: &str = "Usage: discord-lsp-presence wrap -- <COMMAND> [ARGS...]";
+
+/// Notifications relayed from the wrapped server that presence depends on.
+const TAPPED_SERVER_METHODS: &[&str] = &["textDocument/publishDiagnostics"];
+
+/// Messages relayed from the editor that `wrap` also feeds to the built-in
+/// presence server, including the requests it treats as focus hints.
+const TAPPED_CLIENT_METHODS: &[&str] = &[
+    "initialize",
+    "initialized",
+    "shutdown",
+    "exit",
+    "$/setTrace",
+    "textDocument/didOpen",
+    "textDocument/didChange",
+    "textDocument/didSave",
+    "textDocument/didClose",
+    "textDocument/hover",
+    "textDocument/documentHighlight",
+    "textDocument/codeLens",
+    "textDocument/inlayHint",
+    "textDocument/semanticTokens/full",
+];
+
+#[derive(Deserialize)]
+struct Envelope {
+    method: Option<String>,
+    params: Option<serde_json::Value>,
+}
+
+/// One framed JSON-RPC message, kept byte for byte so it can be forwarded
+/// unchanged.
+pub struct Message {
+    raw: Vec<u8>,
+    body_start: usize,
+}
+
+impl Message {
+    pub fn body(&self) -> &[u8] {
+        &self.raw[self.body_start..]
+    }
+}
+
+/// Reads the next message, or `None` once the stream is closed.
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Option<Message>> {
+    let mut raw = Vec::new();
+    let mut content_length = None;
+
+    loop {
+        let start = raw.len();
+        if reader.read_until(b'\n', &mut raw).await? == 0 {
+            return Ok(None);
+        }
+        let line = String::from_utf8_lossy(&raw[start..]);
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(length) = content_length else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "message without Content-Length header",
+        ));
+    };
+    let body_start = raw.len();
+    raw.resize(body_start + length, 0);
+    reader.read_exact(&mut raw[body_start..]).await?;
+    Ok(Some(Message { raw, body_start }))
+}
+
+/// Copies messages from `reader` to `writer` until either side closes,
+/// sending the body of every message whose method is in `methods` to `tap`.
+async fn relay<R, W>(
+    mut reader: R,
+    mut writer: W,
+    methods: &[&str],
+    tap: Option<mpsc::UnboundedSender<Vec<u8>>>,
+) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    while let Some(message) = read_message(&mut reader).await? {
+        writer.write_all(&message.raw).await?;
+        writer.flush().await?;
+
+        let Some(tap) = &tap else {
+            continue;
+        };
+        if let Ok(Envelope {
+            method: Some(method),
+            ..
+        }) = serde_json::from_slice(message.body())
+            && methods.contains(&method.as_str())
+        {
+            let _ = tap.send(message.body().to_vec());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the command following `--`, or all arguments if there is none.
+pub fn parse_command(args: &[String]) -> Option<&[String]> {
+    let command = match args.iter().position(|arg| arg == "--") {
+        Some(i) => &args[i + 1..],
+        None => args,
+    };
+    (!command.is_empty()).then_some(command)
+}
+
+/// The language server being relayed to.
+struct WrappedServer {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl WrappedServer {
+    fn spawn(command: &[String]) -> Option<Self> {
+        let spawned = Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Failed to start {}: {}", command[0], e);
+                return None;
+            }
+        };
+        Some(Self {
+            name: command[0].clone(),
+            stdin: child.stdin.take()?,
+            stdout: child.stdout.take()?,
+            child,
+        })
+    }
+
+    /// Relays the editor's messages to the server and the server's messages
+    /// back until the server closes its output, tapping both directions.
+    /// Returns the server's exit code.
+    async fn relay(
+        self,
+        client_methods: &'static [&'static str],
+        server_methods: &'static [&'static str],
+        tap: mpsc::UnboundedSender<Vec<u8>>,
+    ) -> i32 {
+        let Self {
+            name,
+            mut child,
+            stdin,
+            stdout,
+        } = self;
+
+        tokio::spawn(relay(
+            BufReader::new(tokio::io::stdin()),
+            stdin,
+            client_methods,
+            Some(tap.clone()),
+        ));
+        let to_client = relay(
+            BufReader::new(stdout),
+            tokio::io::stdout(),
+            server_methods,
+            Some(tap),
+        );
+        if let Err(e) = to_client.await {
+            eprintln!("Failed to relay messages from {}: {}", name, e);
+        }
+
+        match child.wait().await {
+            Ok(status) => status.code().unwrap_or(1),
+            Err(_) => 1,
+        }
+    }
+}
+
+/// Entry point of `discord-lsp-presence proxy`, which runs another language
+/// server, relays its traffic unchanged and reports the diagnostics it
+/// publishes to the presence servers started by the same editor. Returns the
+/// process exit code.
+pub async fn run_proxy(args: &[String]) -> i32 {
+    let Some(command) = parse_command(args) else {
+        eprintln!("{}", PROXY_USAGE);
+        return 2;
+    };
+    let Some(server) = WrappedServer::spawn(command) else {
+        return 1;
+    };
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(report_diagnostics(rx));
+    server.relay(&[], TAPPED_SERVER_METHODS, tx).await
+}
+
+/// Runs `command` like [`run_proxy`], additionally feeding the editor's
+/// document notifications and the server's diagnostics to the in-process
+/// presence `service`, whose own output is discarded. Returns the wrapped
+/// server's exit code.
+pub async fn run_wrap<S: LanguageServer>(
+    command: &[String],
+    service: LspService<S>,
+    socket: ClientSocket,
+) -> i32 {
+    let Some(server) = WrappedServer::spawn(command) else {
+        return 1;
+    };
+
+    let (mut presence_input, presence_reader) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(Server::new(presence_reader, tokio::io::sink(), socket).serve(service));
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(body) = rx.recv().await {
+            let header = format!("Content-Length: {}\r\n\r\n", body.len());
+            if presence_input.write_all(header.as_bytes()).await.is_err()
+                || presence_input.write_all(&body).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    server
+        .relay(TAPPED_CLIENT_METHODS, TAPPED_SERVER_METHODS, tx)
+        .await
+}
+
+#[cfg(unix)]
+async fn report_diagnostics(mut rx: mpsc::UnboundedReceiver<Vec<u8>>) {
+    use crate::control::{ControlRequest, notify};
+    use crate::instance::InstanceCoordinator;
+    use crate::state::DiagnosticCounts;
+    use tower_lsp::lsp_types::PublishDiagnosticsParams;
+
+    while let Some(body) = rx.recv().await {
+        let Some(params) = serde_json::from_slice::<Envelope>(&body)
+            .ok()
+            .and_then(|envelope| envelope.params)
+            .and_then(|params| serde_json::from_value::<PublishDiagnosticsParams>(params).ok())
+        else {
+            continue;
+        };
+        let counts = DiagnosticCounts::from_diagnostics(&params.diagnostics);
+        let request = ControlRequest::Diagnostics {
+            uri: params.uri.to_string(),
+            errors: counts.errors,
+            warnings: counts.warnings,
+        };
+        let siblings = tokio::task::spawn_blocking(InstanceCoordinator::siblings)
+            .await
+            .unwrap_or_default();
+        notify(&siblings, &request).await;
+    }
+}
+
+#[cfg(not(unix))]
+async fn report_diagnostics(mut rx: mpsc::UnboundedReceiver<Vec<u8>>) {
+    while rx.recv().await.is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn relays_messages_unchanged() {
+        let body = r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.rs","diagnostics":[]}}"#;
+        let input = format!(
+            "Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut output = Vec::new();
+
+        relay(
+            input.as_bytes(),
+            &mut output,
+            TAPPED_SERVER_METHODS,
+            Some(tx),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output, input.as_bytes());
+        assert_eq!(rx.recv().await.unwrap(), body.as_bytes());
+    }
+}
@@ -0,0 +1,247 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[schemars(extend("enum" = [
+    "mon", "tue", "wed", "thu", "fri", "sat", "sun",
+    "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday",
+]))]
+pub enum Weekday {
+    #[serde(alias = "monday")]
+    Mon,
+    #[serde(alias = "tuesday")]
+    Tue,
+    #[serde(alias = "wednesday")]
+    Wed,
+    #[serde(alias = "thursday")]
+    Thu,
+    #[serde(alias = "friday")]
+    Fri,
+    #[serde(alias = "saturday")]
+    Sat,
+    #[serde(alias = "sunday")]
+    Sun,
+}
+
+impl Weekday {
+    /// Converts a `tm_wday`-style index, where 0 is Sunday.
+    fn from_index(index: u32) -> Self {
+        match index % 7 {
+            0 => Self::Sun,
+            1 => Self::Mon,
+            2 => Self::Tue,
+            3 => Self::Wed,
+            4 => Self::Thu,
+            5 => Self::Fri,
+            _ => Self::Sat,
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Self::Mon => Self::Sun,
+            Self::Tue => Self::Mon,
+            Self::Wed => Self::Tue,
+            Self::Thu => Self::Wed,
+            Self::Fri => Self::Thu,
+            Self::Sat => Self::Fri,
+            Self::Sun => Self::Sat,
+        }
+    }
+}
+
+/// A time of day in minutes since midnight, written as `"HH:MM"`.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String")]
+#[schemars(extend("pattern" = "^[0-9]{1,2}:[0-9]{2}$"))]
+pub struct TimeOfDay(u32);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parsed = value.split_once(':').and_then(|(h, m)| {
+            let hours: u32 = h.trim().parse().ok()?;
+            let minutes: u32 = m.trim().parse().ok()?;
+            (hours <= 24 && minutes < 60 && hours * 60 + minutes <= 24 * 60)
+                .then_some(hours * 60 + minutes)
+        });
+        parsed
+            .map(TimeOfDay)
+            .ok_or_else(|| format!("invalid time of day `{}`, expected HH:MM", value))
+    }
+}
+
+/// A recurring window during which presence is turned off.
+///
+/// Windows whose `end` is before their `start` wrap past midnight, and
+/// `days` refers to the day the window starts on.
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(deny_unknown_fields, transform = crate::config::drop_null_defaults)]
+pub struct QuietHours {
+    #[serde(default)]
+    pub days: Option<Vec<Weekday>>,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl QuietHours {
+    fn applies_on(&self, day: Weekday) -> bool {
+        self.days.as_ref().is_none_or(|days| days.contains(&day))
+    }
+
+    fn contains(&self, day: Weekday, minute: u32) -> bool {
+        let TimeOfDay(start) = self.start;
+        let TimeOfDay(end) = self.end;
+
+        if start <= end {
+            self.applies_on(day) && (start..end).contains(&minute)
+        } else {
+            (self.applies_on(day) && minute >= start)
+                || (self.applies_on(day.previous()) && minute < end)
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[schemars(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    #[serde(default)]
+    pub quiet_hours: Vec<QuietHours>,
+}
+
+impl ScheduleConfig {
+    pub fn is_empty(&self) -> bool {
+        self.quiet_hours.is_empty()
+    }
+
+    pub fn is_quiet_at(&self, day: Weekday, minute: u32) -> bool {
+        self.quiet_hours.iter().any(|q| q.contains(day, minute))
+    }
+
+    pub fn is_quiet_now(&self) -> bool {
+        let (day, minute) = local_now();
+        self.is_quiet_at(day, minute)
+    }
+}
+
+/// Longest duration [`parse_duration`] accepts.
+const MAX_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Parses durations such as `30m`, `1h30m`, `90s` or a bare number of minutes,
+/// up to a year.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let total = match text.parse::<u64>() {
+        Ok(minutes) => minutes.checked_mul(60)?,
+        Err(_) => parse_units(text)?,
+    };
+    let duration = Duration::from_secs(total);
+    (total > 0 && duration <= MAX_DURATION).then_some(duration)
+}
+
+/// Sums the `<number><unit>` parts of a duration, in seconds.
+fn parse_units(text: &str) -> Option<u64> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().ok()?;
+        number.clear();
+        let unit = match c {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(value.checked_mul(unit)?)?;
+    }
+    number.is_empty().then_some(total)
+}
+
+/// Formats a duration in the same units accepted by [`parse_duration`].
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, (secs % 3600) / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, 0) => format!("{}m", m),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, 0, _) => format!("{}h", h),
+        (h, m, _) => format!("{}h{}m", h, m),
+    }
+}
+
+#[cfg(unix)]
+fn local_now() -> (Weekday, u32) {
+    // SAFETY: `time` accepts a null pointer, and `localtime_r` only writes
+    // into the provided `tm`, which is fully initialised on success.
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::MaybeUninit::<libc::tm>::zeroed();
+        if libc::localtime_r(&now, tm.as_mut_ptr()).is_null() {
+            return utc_now();
+        }
+        let tm = tm.assume_init();
+        (
+            Weekday::from_index(tm.tm_wday as u32),
+            (tm.tm_hour * 60 + tm.tm_min) as u32,
+        )
+    }
+}
+
+#[cfg(not(unix))]
+fn local_now() -> (Weekday, u32) {
+    utc_now()
+}
+
+fn utc_now() -> (Weekday, u32) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let days = secs / 86_400;
+    // 1970-01-01 was a Thursday.
+    let day = Weekday::from_index(((days + 4) % 7) as u32);
+    (day, ((secs % 86_400) / 60) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("45"), Some(Duration::from_secs(45 * 60)));
+        assert_eq!(parse_duration("10x"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("18446744073709551615s"), None);
+        assert_eq!(parse_duration("18446744073709551615"), None);
+        assert_eq!(parse_duration("400d"), None);
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight() {
+        let schedule: ScheduleConfig = toml::from_str(
+            r#"
+            [[quiet_hours]]
+            days = ["fri"]
+            start = "22:00"
+            end = "02:00"
+            "#,
+        )
+        .unwrap();
+
+        assert!(schedule.is_quiet_at(Weekday::Fri, 23 * 60));
+        assert!(schedule.is_quiet_at(Weekday::Sat, 60));
+        assert!(!schedule.is_quiet_at(Weekday::Sat, 23 * 60));
+        assert!(!schedule.is_quiet_at(Weekday::Fri, 60));
+    }
+}
@@ -1,49 +1,132 @@
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::path::PathBuf;
+use std::time::Duration;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity};
+use url::Url;
+
+use crate::clock::Clock;
 
 pub struct FileState {
+    pub uri: Url,
     pub filename: String,
     pub workspace: String,
-    pub start_time: Instant,
+    pub repository: Option<String>,
+    /// When the file was switched to, as a reading of [`Clock::now`].
+    pub start_time: Duration,
 }
 
 impl FileState {
-    pub fn new(filename: String, workspace: String) -> Self {
+    pub fn new(
+        uri: Url,
+        filename: String,
+        workspace: String,
+        repository: Option<String>,
+        clock: &dyn Clock,
+    ) -> Self {
         Self {
+            uri,
             filename,
             workspace,
-            start_time: Instant::now(),
+            repository,
+            start_time: clock.now(),
         }
     }
 
-    pub fn get_start_timestamp(&self) -> u64 {
-        let now = SystemTime::now();
-        let elapsed = self.start_time.elapsed();
-        now.duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            .saturating_sub(elapsed.as_secs())
+    pub fn get_start_timestamp(&self, clock: &dyn Clock) -> u64 {
+        clock.to_unix(self.start_time)
     }
 }
 
 pub struct WorkspaceState {
     pub workspace: String,
-    pub start_time: Instant,
+    /// When work on the workspace started, as a reading of [`Clock::now`].
+    pub start_time: Duration,
 }
 
 impl WorkspaceState {
-    pub fn new(workspace: String) -> Self {
+    pub fn new(workspace: String, clock: &dyn Clock) -> Self {
+        Self {
+            workspace,
+            start_time: clock.now(),
+        }
+    }
+
+    pub fn with_start_timestamp(workspace: String, start_timestamp: u64, clock: &dyn Clock) -> Self {
+        Self {
+            workspace,
+            start_time: clock.at_unix(start_timestamp),
+        }
+    }
+
+    pub fn get_start_timestamp(&self, clock: &dyn Clock) -> u64 {
+        clock.to_unix(self.start_time)
+    }
+}
+
+/// The workspace shown while no document is open, and when that started.
+pub struct IdleState {
+    pub workspace: String,
+    pub repository: Option<String>,
+    /// The workspace root, or the last document closed in it.
+    pub path: Option<PathBuf>,
+    /// As a reading of [`Clock::now`].
+    pub start_time: Duration,
+}
+
+impl IdleState {
+    pub fn new(
+        workspace: String,
+        repository: Option<String>,
+        path: Option<PathBuf>,
+        clock: &dyn Clock,
+    ) -> Self {
         Self {
             workspace,
-            start_time: Instant::now(),
+            repository,
+            path,
+            start_time: clock.now(),
         }
     }
 
-    pub fn get_start_timestamp(&self) -> u64 {
-        let now = SystemTime::now();
-        let elapsed = self.start_time.elapsed();
-        now.duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            .saturating_sub(elapsed.as_secs())
+    pub fn get_start_timestamp(&self, clock: &dyn Clock) -> u64 {
+        clock.to_unix(self.start_time)
+    }
+}
+
+/// A free-form status set by the user that overrides the templated details
+/// until it is cleared or expires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomStatus {
+    pub message: String,
+    pub small_image: Option<String>,
+    /// As a reading of [`Clock::now`].
+    pub expires_at: Option<Duration>,
+}
+
+impl CustomStatus {
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.expires_at.is_some_and(|at| at <= clock.now())
+    }
+}
+
+/// Error and warning counts for a document, as published by the language
+/// server wrapped in proxy mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiagnosticCounts {
+    pub errors: u32,
+    pub warnings: u32,
+}
+
+impl DiagnosticCounts {
+    pub fn from_diagnostics(diagnostics: &[Diagnostic]) -> Self {
+        let mut counts = Self::default();
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                // Servers omitting the severity usually mean an error.
+                Some(DiagnosticSeverity::ERROR) | None => counts.errors += 1,
+                Some(DiagnosticSeverity::WARNING) => counts.warnings += 1,
+                _ => {}
+            }
+        }
+        counts
     }
 }
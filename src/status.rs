@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::schedule::format_duration;
+
+/// Snapshot of what the server is broadcasting, returned by the
+/// `discord-presence/status` request, the `discord-presence.status` command
+/// and `ctl status`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    pub pid: u32,
+    pub enabled: bool,
+    pub resume_at: Option<u64>,
+    pub active: bool,
+    pub discord_connected: bool,
+    pub details: Option<String>,
+    pub state: Option<String>,
+    pub filename: Option<String>,
+    pub workspace: Option<String>,
+    pub language: Option<String>,
+    pub timer_start: Option<u64>,
+    pub custom_status: Option<String>,
+    pub profile: Option<String>,
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match (self.enabled, self.resume_at) {
+            (true, _) => "enabled".to_string(),
+            (false, Some(ts)) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                let remaining = Duration::from_secs(ts.saturating_sub(now));
+                format!("paused, resuming in {}", format_duration(remaining))
+            }
+            (false, None) => "paused".to_string(),
+        };
+        write!(
+            f,
+            "pid {}: {}, {}{}",
+            self.pid,
+            state,
+            if self.discord_connected {
+                "connected to Discord"
+            } else {
+                "not connected to Discord"
+            },
+            if self.active { "" } else { " (sibling active)" },
+        )?;
+        if let Some(filename) = &self.filename {
+            write!(f, ", {}", filename)?;
+            if let Some(workspace) = &self.workspace {
+                write!(f, " in {}", workspace)?;
+            }
+        }
+        if let (Some(details), Some(state)) = (&self.details, &self.state) {
+            write!(f, ", showing \"{} / {}\"", details, state)?;
+        }
+        if let Some(profile) = &self.profile {
+            write!(f, " with profile {}", profile)?;
+        }
+        Ok(())
+    }
+}